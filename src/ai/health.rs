@@ -0,0 +1,165 @@
+use crate::ai::localization::{
+    ErrorCalculator, PoseMCL, ResampleNoiseCalculator, WeightCalculator,
+};
+use std::collections::VecDeque;
+
+/// The mode of a localizer, chosen by a `HealthMonitor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalizationMode {
+    /// The robot is localized, so a narrow kernel and few particles are enough to track it
+    Tracking,
+    /// The robot may be lost, so a wide kernel and many particles are used to find it again
+    Recovery,
+}
+
+/// The settings applied to a localizer in a `LocalizationMode`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModeSettings {
+    pub max_particle_count: usize,
+    pub kernel_width: f64,
+}
+
+/// Switches a localizer between tracking and recovery
+/// based on the health of its sensors and the confidence of its belief
+///
+/// `tracking` and `recovery` are the settings applied to the localizer in each mode
+///
+/// `max_failure_rate` is the fraction of the last `window` sensor readings which may fail
+/// before switching to recovery
+///
+/// `max_spread` is the root mean square distance of the belief from its average
+/// above which the localizer switches to recovery
+pub struct HealthMonitor {
+    pub tracking: ModeSettings,
+    pub recovery: ModeSettings,
+    pub max_failure_rate: f64,
+    pub max_spread: f64,
+    window: usize,
+    readings: VecDeque<bool>,
+    mode: LocalizationMode,
+}
+
+impl HealthMonitor {
+    /// Creates a new health monitor which starts in tracking mode
+    pub fn new(
+        tracking: ModeSettings,
+        recovery: ModeSettings,
+        max_failure_rate: f64,
+        max_spread: f64,
+        window: usize,
+    ) -> Self {
+        Self {
+            tracking,
+            recovery,
+            max_failure_rate,
+            max_spread,
+            window,
+            readings: VecDeque::with_capacity(window),
+            mode: LocalizationMode::Tracking,
+        }
+    }
+
+    pub fn mode(&self) -> LocalizationMode {
+        self.mode
+    }
+
+    /// Records whether the latest reading of a sensor was healthy
+    /// (e.g. whether a scan was received), forgetting readings older than the window
+    pub fn record_reading(&mut self, healthy: bool) {
+        self.readings.push_back(healthy);
+        while self.readings.len() > self.window {
+            self.readings.pop_front();
+        }
+    }
+
+    /// The fraction of the readings in the window which were not healthy
+    pub fn failure_rate(&self) -> f64 {
+        if self.readings.is_empty() {
+            return 0.;
+        }
+        self.readings.iter().filter(|&&healthy| !healthy).count() as f64
+            / self.readings.len() as f64
+    }
+
+    /// Chooses the mode given the failure rate of the sensors and the `spread` of the belief
+    /// (the root mean square distance of the belief from its average)
+    pub fn update_mode(&mut self, spread: f64) -> LocalizationMode {
+        self.mode = if self.failure_rate() > self.max_failure_rate
+            || spread.is_nan()
+            || spread > self.max_spread
+        {
+            LocalizationMode::Recovery
+        } else {
+            LocalizationMode::Tracking
+        };
+        self.mode
+    }
+
+    /// Chooses the mode given the failure rate of the sensors and the belief of `localizer`,
+    /// then applies the settings of that mode to `localizer`
+    pub fn apply<W, L, O, R, Y, Z>(
+        &mut self,
+        localizer: &mut PoseMCL<W, L, O, R, Y, Z>,
+    ) -> LocalizationMode
+    where
+        W: WeightCalculator + Send + Sync,
+        L: ErrorCalculator<Y> + Send + Sync,
+        O: ErrorCalculator<Z> + Send + Sync,
+        R: ResampleNoiseCalculator + Send + Sync,
+        Y: Sync + Send,
+        Z: Sync + Send,
+    {
+        let covariance = localizer.get_covariance();
+        let mode = self.update_mode((covariance[1][1] + covariance[2][2]).sqrt());
+        let settings = match mode {
+            LocalizationMode::Tracking => self.tracking,
+            LocalizationMode::Recovery => self.recovery,
+        };
+        localizer.set_max_particle_count(settings.max_particle_count);
+        localizer.set_kernel_width(settings.kernel_width);
+        mode
+    }
+}
+
+/// Fraction of the resampled belief drawn from distinct particles below which it is impoverished
+pub const MIN_UNIQUE_ANCESTOR_FRACTION: f64 = 0.1;
+
+/// Effective sample size as a fraction of the number of particles below which the weights are degenerate
+pub const MIN_EFFECTIVE_SAMPLE_FRACTION: f64 = 0.1;
+
+/// Fraction of the belief outside of the free space of the map above which it is out of bounds
+pub const MAX_OUT_OF_BOUNDS_FRACTION: f64 = 0.1;
+
+/// Checks of a belief for common failure modes of MCL with suggested remedies,
+/// see `PoseMCL::health_report`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    /// The fraction of the belief drawn from distinct particles at the last observation update,
+    /// or `None` if there hasn't been one since the belief was (re)initialized
+    pub unique_ancestor_fraction: Option<f64>,
+    /// The effective sample size of the weights at the last observation update
+    /// as a fraction of the number of particles which were weighted,
+    /// or `None` if there hasn't been one since the belief was (re)initialized
+    pub effective_sample_fraction: Option<f64>,
+    /// The number of particles outside of the map or inside of an obstacle
+    pub out_of_bounds_count: usize,
+    /// The number of particles and weights which are NaN or infinite
+    pub non_finite_count: usize,
+    /// Whether the belief is made of copies of a few particles (see `MIN_UNIQUE_ANCESTOR_FRACTION`)
+    pub impoverished: bool,
+    /// Whether a few particles had almost all of the weight (see `MIN_EFFECTIVE_SAMPLE_FRACTION`)
+    pub degenerate: bool,
+    /// Whether many particles are outside of the free space of the map (see `MAX_OUT_OF_BOUNDS_FRACTION`)
+    pub out_of_bounds: bool,
+    /// Whether any particle or weight is NaN or infinite
+    pub nan_contaminated: bool,
+    /// A suggested remedy for each failure mode which was detected
+    pub remedies: Vec<&'static str>,
+}
+
+impl HealthReport {
+    /// Whether no failure mode was detected
+    pub fn is_healthy(&self) -> bool {
+        self.remedies.is_empty()
+    }
+}
@@ -1,6 +1,9 @@
-use crate::utility::{KinematicState, Point, Pose};
+use crate::{
+    ai::localization::Localizer,
+    utility::{KinematicState, Point, Pose},
+};
 use nalgebra::{base::allocator::Allocator, Const, DefaultAllocator, DimSub};
-use nalgebra::{OMatrix, ToTypenum};
+use nalgebra::{Matrix3, OMatrix, ToTypenum};
 #[derive(Debug, Clone, Copy)]
 
 /// The configuration of a given Unscented Kalman Filter with specific parameters.
@@ -17,6 +20,32 @@ pub struct Config {
     pub kappa: f64,
 }
 
+impl Config {
+    /// The standard recommended parameters for a filter with an `n_state` dimensional
+    /// state and a Gaussian prior: α = 1e-3, β = 2, κ = 3 - n.
+    pub fn auto_tune(n_state: usize) -> Config {
+        let config = Config {
+            alpha: 1e-3,
+            beta: 2.,
+            kappa: 3. - n_state as f64,
+        };
+        debug_assert!(config.is_valid(n_state));
+        config
+    }
+
+    /// Whether the parameters are usable for a filter with an `n_state` dimensional state.
+    ///
+    /// The sigma point weights are divided by n + λ = α²(n + κ), so α must be nonzero
+    /// and n + κ must be positive.
+    pub fn is_valid(&self, n_state: usize) -> bool {
+        self.alpha.is_finite()
+            && self.beta.is_finite()
+            && self.kappa.is_finite()
+            && self.alpha != 0.
+            && n_state as f64 + self.kappa > 0.
+    }
+}
+
 impl Default for Config {
     // default configuration, assuming a normal distribution with alpha = 0.00001
     fn default() -> Config {
@@ -32,9 +61,18 @@ const LOCALIZATION_STATE_D: usize = 6;
 const LOCALIZATION_SENSOR_D: usize = 6;
 const LOCALIZATION_STATE_D_1: usize = LOCALIZATION_STATE_D * 2 + 1;
 
+/// Size of the diagonal added to a singular innovation covariance in `measurement_update`,
+/// relative to the mean of its diagonal (or 1 if that is smaller)
+pub const COV_ZZ_REGULARIZATION: f64 = 1e-9;
+
 /// Trait for a general Kalman Filter with arbitrary state and sensor dimension space.
 /// In each time step, run the control update (with struct-specified control transform)
 /// and sensor update (with struct-specified sensor transform).
+///
+/// Predictions and measurements don't have to alternate: predictions can run at the rate of
+/// the control input (e.g. an IMU) with measurements whenever they arrive. A measurement uses
+/// the sigma points of the latest prediction, or new sigma points of the current state if there
+/// hasn't been a prediction since the last measurement (see `predicted`).
 pub trait KalmanFilter<const STATE_D: usize, const STATE_D_1: usize, const SENSOR_D: usize>
 where
     Const<STATE_D>: ToTypenum + DimSub<Const<1_usize>>,
@@ -83,6 +121,14 @@ where
 
     fn set_config(&mut self, config: Config);
 
+    /// The recommended configuration for this filter's state dimension (see `Config::auto_tune`).
+    fn auto_tune() -> Config
+    where
+        Self: Sized,
+    {
+        Config::auto_tune(STATE_D)
+    }
+
     /// Returns Kalman Filter expected state.
     fn known_state(&self) -> OMatrix<f64, Const<1_usize>, Const<STATE_D>>;
 
@@ -96,6 +142,12 @@ where
         covariance_matrix: OMatrix<f64, Const<STATE_D>, Const<STATE_D>>,
     );
 
+    /// Whether the control sigma points are from a prediction since the last measurement
+    /// (and so match the current state and covariance).
+    fn predicted(&self) -> bool;
+
+    fn set_predicted(&mut self, predicted: bool);
+
     /// Generate the sigma points from the covariance matrix and state. Follows the algorithm
     /// described in https://github.com/RoboticsTeam4904/wiki/wiki/Unscented-Kalman-Filters.
     fn gen_sigma_matrix(&mut self) {
@@ -104,6 +156,10 @@ where
 
         let known_state = self.known_state();
         let config = self.config();
+        debug_assert!(
+            config.is_valid(STATE_D),
+            "Invalid Unscented Kalman Filter configuration: α must be nonzero and n + κ positive"
+        );
         let lambda = (config.alpha.powi(2)) * (STATE_D as f64 + config.kappa) - STATE_D as f64;
 
         // Generate the eigenvector and eigenvalues decomposition of the covariance matrix
@@ -148,6 +204,9 @@ where
 
     /// Prediction update step. Taking a control update, and a corresponding noise,
     /// map the sigma points and update the covariance matrix.
+    ///
+    /// `time` is the time elapsed since the previous prediction rather than a timestamp,
+    /// so several predictions between measurements move the state by their total time.
     fn prediction_update(
         &mut self,
         time: f64,
@@ -207,18 +266,29 @@ where
         }
         temp_covariance_matrix += q;
         self.set_covariance_matrix(temp_covariance_matrix);
+        self.set_predicted(true);
     }
 
     // Transform data in the state space to sensor data.
     fn sensor_transform(&self, row: &[f64]) -> OMatrix<f64, Const<1>, Const<SENSOR_D>>;
 
-    // Measurement step of the Kalman Filter. Map sigma points to sensor space, intersect
-    // with measurements, and find the new expected state and covariance matrix.
+    /// Measurement step of the Kalman Filter. Map sigma points to sensor space, intersect
+    /// with measurements, and find the new expected state and covariance matrix.
+    ///
+    /// If the innovation covariance is singular (e.g. from a degenerate measurement), it is
+    /// regularized with a small diagonal (see `COV_ZZ_REGULARIZATION`). Returns `Err` without
+    /// changing the state if it still can't be inverted (e.g. if it isn't finite).
     fn measurement_update(
         &mut self,
         sensor_input: OMatrix<f64, Const<1>, Const<SENSOR_D>>,
         r: OMatrix<f64, Const<SENSOR_D>, Const<SENSOR_D>>, // sensor error covariance matrix
-    ) {
+    ) -> Result<(), &'static str> {
+        // The sigma points of the last prediction are out of date once a measurement
+        // has changed the state, and there are none before the first prediction.
+        if !self.predicted() {
+            self.gen_sigma_matrix();
+        }
+
         let config = self.config();
         let sigma_elements: Vec<f64> = self
             .control_sigma_matrix()
@@ -285,10 +355,22 @@ where
                 };
         }
 
-        let k: OMatrix<f64, Const<STATE_D>, Const<SENSOR_D>> = cov_xz
-            * cov_zz.clone().try_inverse().unwrap_or_else(|| {
-                panic!("Inverse of covariance matrix z, z failed");
-            });
+        let is_finite = |inverse: &OMatrix<f64, Const<SENSOR_D>, Const<SENSOR_D>>| {
+            inverse.iter().all(|v| v.is_finite())
+        };
+        let inverse_cov_zz = match cov_zz.clone().try_inverse() {
+            Some(inverse) if is_finite(&inverse) => inverse,
+            _ => {
+                let scale = (cov_zz.trace() / SENSOR_D as f64).abs().max(1.);
+                (cov_zz.clone()
+                    + OMatrix::<f64, Const<SENSOR_D>, Const<SENSOR_D>>::identity()
+                        * (COV_ZZ_REGULARIZATION * scale))
+                    .try_inverse()
+                    .filter(is_finite)
+                    .ok_or("Inverse of covariance matrix z, z failed")?
+            }
+        };
+        let k: OMatrix<f64, Const<STATE_D>, Const<SENSOR_D>> = cov_xz * inverse_cov_zz;
 
         let sensor_diff: OMatrix<f64, Const<SENSOR_D>, Const<1>> =
             (sensor_input - sensor_predicted).transpose();
@@ -296,6 +378,8 @@ where
 
         self.set_known_state(self.known_state() + known_state_transpose.transpose());
         self.set_covariance_matrix(self.covariance_matrix() - (k.clone() * cov_zz * k.transpose()));
+        self.set_predicted(false);
+        Ok(())
     }
 }
 
@@ -306,6 +390,7 @@ pub struct LocalizationFilter {
         OMatrix<f64, Const<LOCALIZATION_STATE_D_1>, Const<LOCALIZATION_STATE_D>>,
     sensor_sigma_matrix: OMatrix<f64, Const<LOCALIZATION_STATE_D_1>, Const<LOCALIZATION_SENSOR_D>>,
     config: Config,
+    predicted: bool,
 }
 
 impl KalmanFilter<LOCALIZATION_STATE_D, LOCALIZATION_STATE_D_1, LOCALIZATION_SENSOR_D>
@@ -330,6 +415,7 @@ impl KalmanFilter<LOCALIZATION_STATE_D, LOCALIZATION_STATE_D_1, LOCALIZATION_SEN
                 Const<LOCALIZATION_SENSOR_D>,
             >::from_element(0.),
             config,
+            predicted: false,
         }
     }
 
@@ -398,6 +484,14 @@ impl KalmanFilter<LOCALIZATION_STATE_D, LOCALIZATION_STATE_D_1, LOCALIZATION_SEN
         self.covariance_matrix = covariance_matrix;
     }
 
+    fn predicted(&self) -> bool {
+        self.predicted
+    }
+
+    fn set_predicted(&mut self, predicted: bool) {
+        self.predicted = predicted;
+    }
+
     fn control_update(
         &self,
         row: &[f64],
@@ -433,3 +527,20 @@ impl KalmanFilter<LOCALIZATION_STATE_D, LOCALIZATION_STATE_D_1, LOCALIZATION_SEN
         OMatrix::<f64, Const<1>, Const<LOCALIZATION_SENSOR_D>>::from_vec(sigma_state)
     }
 }
+
+impl Localizer for LocalizationFilter {
+    fn prediction(&self) -> Pose {
+        Pose {
+            angle: self.known_state[(0, 0)],
+            position: Point {
+                x: self.known_state[(0, 1)],
+                y: self.known_state[(0, 2)],
+            },
+        }
+    }
+
+    /// The covariance of the angle and position of the state
+    fn covariance(&self) -> Matrix3<f64> {
+        Matrix3::from_fn(|row, col| self.covariance_matrix[(row, col)])
+    }
+}
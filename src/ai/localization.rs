@@ -1,8 +1,13 @@
 use crate::{
-    map::Map2D,
+    ai::health::{
+        HealthReport, MAX_OUT_OF_BOUNDS_FRACTION, MIN_EFFECTIVE_SAMPLE_FRACTION,
+        MIN_UNIQUE_ANCESTOR_FRACTION,
+    },
+    map::{Map2D, Raycaster},
     sensors::Sensor,
-    utility::{Point, Pose},
+    utility::{clamp, Point, Pose},
 };
+use nalgebra::Matrix3;
 use rand::{distributions::WeightedIndex, prelude::*};
 use rayon::prelude::*;
 use statrs::function::erf::erf;
@@ -11,16 +16,26 @@ use std::{f64::consts::*, marker::PhantomData, sync::Arc};
 struct PoseBelief;
 
 impl PoseBelief {
-    fn new(max_particle_count: usize, max_position: Point) -> Vec<Pose> {
-        let mut belief = Vec::with_capacity(max_particle_count);
-        for _ in 0..max_particle_count {
-            belief.push(Pose::random(
-                0.0..2. * PI,
-                0.0..max_position.x,
-                0.0..max_position.y,
-            ));
+    /// Spreads `max_particle_count` particles over the free space of `map`
+    fn new<M: Raycaster>(max_particle_count: usize, map: &M) -> Vec<Pose> {
+        let mut rng = thread_rng();
+        (0..max_particle_count)
+            .map(|_| Self::random_particle(map, &mut rng))
+            .collect()
+    }
+
+    /// A particle with a random angle at a random point in the free space of `map`,
+    /// or anywhere in `map` if no free space was found (which `PoseMCL::health_report` flags)
+    fn random_particle<M: Raycaster, R: Rng>(map: &M, rng: &mut R) -> Pose {
+        let size = map.size();
+        let position = map.sample_free_point(rng).unwrap_or_else(|| Point {
+            x: rng.gen::<f64>() * size.x,
+            y: rng.gen::<f64>() * size.y,
+        });
+        Pose {
+            angle: rng.gen_range(0.0..2. * PI),
+            position,
         }
-        belief
     }
 
     fn from_distributions<T, U>(max_particle_count: usize, distr: (T, (T, T))) -> Vec<Pose>
@@ -46,15 +61,268 @@ impl PoseBelief {
         }
         belief
     }
+
+    /// Finds the root mean square distance of the positions of `belief` from their average
+    fn position_spread(belief: &[Pose]) -> f64 {
+        let mut average = Point::default();
+        for sample in belief {
+            average += sample.position;
+        }
+        average = average / belief.len() as f64;
+        let mut variance = 0.;
+        for sample in belief {
+            variance += (average - sample.position).mag().powi(2);
+        }
+        (variance / belief.len() as f64).sqrt()
+    }
+
+    /// Moves every particle of `belief` by `update`, then drops any particles which are not finite.
+    ///
+    /// Updates which are not finite (e.g. from dividing by zero in an odometry model)
+    /// are skipped so that they do not poison the whole belief.
+    /// If no particles are left, the belief is reinitialized.
+    fn control_update<M: Raycaster>(
+        belief: &mut Vec<Pose>,
+        update: Pose,
+        max_particle_count: usize,
+        map: &M,
+    ) {
+        if update.is_finite() {
+            belief.iter_mut().for_each(|p| *p += update);
+        } else {
+            println!("Skipping control update which is not finite: {:?}", update);
+        }
+        belief.retain(|p| p.is_finite());
+        if belief.is_empty() {
+            *belief = Self::new(max_particle_count, map);
+        }
+    }
+
+    /// Scales `weights` to sum to 1, unless they sum to 0
+    fn normalize_weights(weights: &mut [f64]) {
+        let sum: f64 = weights.iter().sum();
+        if sum > 0. && sum.is_finite() {
+            weights.iter_mut().for_each(|weight| *weight /= sum);
+        }
+    }
+
+    /// Reorders the indices of particles `drawn` during resampling so that, where possible,
+    /// each particle which survived resampling keeps its index in the belief
+    fn stable_order(drawn: &[usize]) -> Vec<usize> {
+        let mut copies = vec![0; drawn.iter().max().map_or(0, |&max| max + 1)];
+        for &idx in drawn {
+            copies[idx] += 1;
+        }
+        let mut order: Vec<Option<usize>> = (0..drawn.len())
+            .map(|slot| match copies.get_mut(slot) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    Some(slot)
+                }
+                _ => None,
+            })
+            .collect();
+        let mut extra_copies = copies
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, &count)| std::iter::repeat(idx).take(count));
+        for slot in order.iter_mut().filter(|slot| slot.is_none()) {
+            *slot = extra_copies.next();
+        }
+        order.into_iter().flatten().collect()
+    }
+
+    /// Greedily groups the particles of `belief` into clusters, adding each particle to the
+    /// first cluster whose average pose is within `cluster_radius` of it (see `Pose::distance_to`).
+    ///
+    /// Returns the average pose and the number of particles of each cluster, largest first
+    fn clusters(belief: &[Pose], cluster_radius: f64, angle_weight: f64) -> Vec<(Pose, usize)> {
+        // the sums of the positions and of the directions of the particles of each cluster,
+        // so that angles are averaged without wrapping around
+        let mut clusters: Vec<(Point, Point, usize)> = Vec::new();
+        let mean = |&(position_sum, direction_sum, count): &(Point, Point, usize)| Pose {
+            angle: direction_sum.y.atan2(direction_sum.x),
+            position: position_sum / count as f64,
+        };
+        for &sample in belief {
+            let cluster = clusters
+                .iter_mut()
+                .find(|cluster| mean(cluster).distance_to(sample, angle_weight) <= cluster_radius);
+            match cluster {
+                Some((position_sum, direction_sum, count)) => {
+                    *position_sum += sample.position;
+                    *direction_sum += Point::polar(sample.angle, 1.);
+                    *count += 1;
+                }
+                None => clusters.push((sample.position, Point::polar(sample.angle, 1.), 1)),
+            }
+        }
+        clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.2));
+        clusters
+            .iter()
+            .map(|cluster| (mean(cluster), cluster.2))
+            .collect()
+    }
+
+    /// Creates a normalized `bins_x` by `bins_y` histogram of the positions of `belief` over `size`
+    /// stored in row-major order (the mass of bin (x, y) is at `y * bins_x + x`)
+    ///
+    /// Positions outside of `size` are counted in the nearest bin
+    fn histogram(belief: &[Pose], size: Point, bins_x: usize, bins_y: usize) -> Vec<f64> {
+        let mut histogram = vec![0.; bins_x * bins_y];
+        if belief.is_empty() || histogram.is_empty() {
+            return histogram;
+        }
+        let mass = 1. / belief.len() as f64;
+        for sample in belief {
+            let bin = |coord: f64, max: f64, bins: usize| {
+                clamp(
+                    (coord / max * bins as f64).floor() as isize,
+                    0,
+                    Some(bins as isize),
+                ) as usize
+            };
+            let x = bin(sample.position.x, size.x, bins_x);
+            let y = bin(sample.position.y, size.y, bins_y);
+            histogram[y * bins_x + x] += mass;
+        }
+        histogram
+    }
 }
 
 pub trait WeightCalculator: Fn(&f64) -> f64 {}
 impl<T: Fn(&f64) -> f64> WeightCalculator for T {}
-pub trait ErrorCalculator<Z>: Fn(&Pose, &Z, &Arc<Map2D>) -> f64 {}
-impl<Z, T: Fn(&Pose, &Z, &Arc<Map2D>) -> f64> ErrorCalculator<Z> for T {}
+pub trait ErrorCalculator<Z, M = Map2D>: Fn(&Pose, &Z, &Arc<M>) -> f64 {}
+impl<Z, M, T: Fn(&Pose, &Z, &Arc<M>) -> f64> ErrorCalculator<Z, M> for T {}
 pub trait ResampleNoiseCalculator: Fn(usize) -> Pose {}
 impl<T: Fn(usize) -> Pose> ResampleNoiseCalculator for T {}
 
+/// Errors at which `debug_check_weight_from_error` samples weights
+const WEIGHT_CHECK_ERRORS: [f64; 8] = [0., 0.1, 0.5, 1., 2., 5., 10., 100.];
+
+/// In debug builds, checks that `weight_from_error` does not increase with error
+/// by sampling it at a few increasing errors, as MCL relies on lower errors having higher weights
+fn debug_check_weight_from_error<W: WeightCalculator>(weight_from_error: &W) {
+    if cfg!(debug_assertions) {
+        for errors in WEIGHT_CHECK_ERRORS.windows(2) {
+            let (lower, higher) = (weight_from_error(&errors[0]), weight_from_error(&errors[1]));
+            debug_assert!(
+                higher <= lower,
+                "weight_from_error must not increase with error, but the weight at error {} is {} and the weight at error {} is {}",
+                errors[0],
+                lower,
+                errors[1],
+                higher
+            );
+        }
+    }
+}
+
+/// Statistics of a belief computed in a single pass over its particles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeliefSummary {
+    /// The average pose of the particles.
+    /// Angles are averaged along the shortest arc from the angle of the first particle,
+    /// so the average is only meaningful for beliefs spanning less than half a turn
+    pub mean: Pose,
+    /// The covariance of the particles in the order angle, x, y
+    pub covariance: [[f64; 3]; 3],
+    /// The differential entropy of a normal distribution with `covariance`.
+    /// Is negative infinity if the particles are all in the same place
+    pub entropy: f64,
+    /// The effective number of particles given their weights, `1 / Σw²` for normalized weights
+    pub effective_sample_size: f64,
+}
+
+impl BeliefSummary {
+    /// Summarizes `belief` with the weights of its particles.
+    /// If the lengths of `belief` and `weights` do not match, the particles are weighted evenly.
+    ///
+    /// The mean and covariance are of the particles themselves, while the weights are only
+    /// used for the effective sample size, as a resampled belief is already distributed by weight
+    pub fn new(belief: &[Pose], weights: &[f64]) -> Self {
+        let count = belief.len() as f64;
+        let reference_angle = belief.first().map(|pose| pose.angle).unwrap_or(0.);
+        let even_weights = weights.len() != belief.len();
+        let mut sums = [0.; 3];
+        let mut products = [[0.; 3]; 3];
+        let (mut weight_sum, mut weight_square_sum) = (0., 0.);
+        for (i, pose) in belief.iter().enumerate() {
+            let mut angle_diff = (pose.angle - reference_angle) % (2. * PI);
+            if angle_diff > PI {
+                angle_diff -= 2. * PI;
+            } else if angle_diff < -PI {
+                angle_diff += 2. * PI;
+            }
+            let values = [angle_diff, pose.position.x, pose.position.y];
+            for row in 0..3 {
+                sums[row] += values[row];
+                for col in 0..3 {
+                    products[row][col] += values[row] * values[col];
+                }
+            }
+            let weight = if even_weights { 1. } else { weights[i] };
+            weight_sum += weight;
+            weight_square_sum += weight * weight;
+        }
+        let mut covariance = [[0.; 3]; 3];
+        for row in 0..3 {
+            for col in 0..3 {
+                covariance[row][col] =
+                    products[row][col] / count - sums[row] * sums[col] / (count * count);
+            }
+        }
+        let determinant = covariance[0][0]
+            * (covariance[1][1] * covariance[2][2] - covariance[1][2] * covariance[2][1])
+            - covariance[0][1]
+                * (covariance[1][0] * covariance[2][2] - covariance[1][2] * covariance[2][0])
+            + covariance[0][2]
+                * (covariance[1][0] * covariance[2][1] - covariance[1][1] * covariance[2][0]);
+        Self {
+            mean: Pose {
+                angle: (reference_angle + sums[0] / count) % (2. * PI),
+                position: Point {
+                    x: sums[1] / count,
+                    y: sums[2] / count,
+                },
+            },
+            covariance,
+            entropy: 0.5 * ((2. * PI * E).powi(3) * determinant.max(0.)).ln(),
+            effective_sample_size: weight_sum * weight_sum / weight_square_sum,
+        }
+    }
+}
+
+impl From<&[Pose]> for BeliefSummary {
+    /// Summarizes `belief` with evenly weighted particles
+    fn from(belief: &[Pose]) -> Self {
+        Self::new(belief, &[])
+    }
+}
+
+/// A localizer whose estimate of the pose of the robot can be compared with that of other localizers
+/// (e.g. to weight each localizer when fusing their estimates)
+pub trait Localizer {
+    /// The estimated pose of the robot
+    fn prediction(&self) -> Pose;
+    /// The covariance of the estimated pose in the order angle, x, y
+    fn covariance(&self) -> Matrix3<f64>;
+    /// How confident the localizer is in its estimate, from 0 (no confidence) to 1 (certain)
+    ///
+    /// By default, this is `1 / (1 + d)` where `d` is the root mean square distance
+    /// of the position from the estimate given by `covariance`
+    fn confidence(&self) -> f64 {
+        let covariance = self.covariance();
+        let spread = (covariance[(1, 1)] + covariance[(2, 2)]).sqrt();
+        if spread.is_nan() {
+            0.
+        } else {
+            1. / (1. + spread)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct DeathCondition {
     pub particle_count_threshold: usize,
     pub particle_concentration_threshold: f64,
@@ -82,6 +350,38 @@ impl DeathCondition {
     }
 }
 
+/// Physical limits on the motion of the robot used to reject implausible control updates
+/// (e.g. spikes from unfiltered odometry)
+///
+/// `max_angular_rate` is the maximum angular velocity of the robot in radians per second
+///
+/// `max_linear_speed` is the maximum linear speed of the robot in distance per second
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct MotionLimits {
+    pub max_angular_rate: Option<f64>,
+    pub max_linear_speed: Option<f64>,
+}
+
+impl MotionLimits {
+    /// Clamps `delta`, the change in pose over `delta_t` seconds, to the limits described by `self`
+    ///
+    /// The direction of the linear motion is preserved
+    pub fn limit(&self, mut delta: Pose, delta_t: f64) -> Pose {
+        if let Some(max_angular_rate) = self.max_angular_rate {
+            let max_angle = max_angular_rate * delta_t;
+            delta.angle = delta.angle.max(-max_angle).min(max_angle);
+        }
+        if let Some(max_linear_speed) = self.max_linear_speed {
+            let max_dist = max_linear_speed * delta_t;
+            let dist = delta.position.mag();
+            if dist > max_dist {
+                delta.position = delta.position * (max_dist / dist);
+            }
+        }
+        delta
+    }
+}
+
 /// A localizer that uses likelyhood-based Monte Carlo Localization
 /// and takes in motion sensor data and `Z` as sensor data
 ///
@@ -100,14 +400,39 @@ impl DeathCondition {
 /// `errors_from_sense` calculates the error of each particle from its sensor data
 ///
 /// `resampling_noise` calculates the amount of noise to add to each particle during resampling
-pub struct PoseMCL<W, L, O, R, Y, Z>
+///
+/// `motion_limits` are the limits applied to control updates,
+/// which are `update_period` seconds apart unless given otherwise (see `control_update_with_dt`)
+///
+/// `weights` are the weights of the particles in the belief from the last observation update
+/// (each resampled particle keeps the weight of the particle it was sampled from),
+/// normalized to sum to 1
+///
+/// `stable_resampling` is whether particles which survive resampling keep their index in the belief
+///
+/// `rng` is the random number generator used to draw particles during resampling
+///
+/// `kernel_width` divides the error of each particle before it is weighted,
+/// so wider kernels give flatter weights (e.g. when recovering from being lost)
+///
+/// `replace_zero_weights` is whether resampling replaces particles with a weight of zero
+/// with fresh uniformly distributed particles
+///
+/// `log_likelihood` is whether errors are negative log-likelihoods, which are shifted
+/// so that the smallest is 0 before they are weighted
+///
+/// `unique_ancestor_fraction` and `effective_sample_fraction` describe the last resampling,
+/// see `HealthReport`, and `effective_sample_size` is that of the weights it was drawn with
+///
+/// The map can be anything which can be raycast (see `Raycaster`), and is a `Map2D` by default
+pub struct PoseMCL<W, L, O, R, Y, Z, M = Map2D>
 where
     W: WeightCalculator,
-    L: ErrorCalculator<Y>,
-    O: ErrorCalculator<Z>,
+    L: ErrorCalculator<Y, M>,
+    O: ErrorCalculator<Z, M>,
     R: ResampleNoiseCalculator,
 {
-    pub map: Arc<Map2D>,
+    pub map: Arc<M>,
     pub belief: Vec<Pose>,
     max_particle_count: usize,
     weight_sum_threshold: f64,
@@ -116,30 +441,43 @@ where
     errors_from_lidar: L,
     errors_from_object: O,
     resampling_noise: R,
+    motion_limits: MotionLimits,
+    update_period: f64,
+    weights: Vec<f64>,
+    stable_resampling: bool,
+    rng: StdRng,
+    kernel_width: f64,
+    replace_zero_weights: bool,
+    log_likelihood: bool,
+    unique_ancestor_fraction: Option<f64>,
+    effective_sample_fraction: Option<f64>,
+    effective_sample_size: Option<f64>,
     lidar_data_type: PhantomData<Y>,
     object_data_type: PhantomData<Z>,
 }
 
-impl<W, L, O, R, Y, Z> PoseMCL<W, L, O, R, Y, Z>
+impl<W, L, O, R, Y, Z, M> PoseMCL<W, L, O, R, Y, Z, M>
 where
     W: WeightCalculator + Send + Sync,
-    L: ErrorCalculator<Y> + Send + Sync,
-    O: ErrorCalculator<Z> + Send + Sync,
+    L: ErrorCalculator<Y, M> + Send + Sync,
+    O: ErrorCalculator<Z, M> + Send + Sync,
     R: ResampleNoiseCalculator + Send + Sync,
     Y: Sync + Send,
     Z: Sync + Send,
+    M: Raycaster + Send + Sync,
 {
     pub fn new(
         max_particle_count: usize,
         weight_sum_threshold: f64,
         death_condition: DeathCondition,
-        map: Arc<Map2D>,
+        map: Arc<M>,
         weight_from_error: W,
         errors_from_lidar: L,
         errors_from_object: O,
         resampling_noise: R,
     ) -> Self {
-        let belief = PoseBelief::new(max_particle_count, map.size);
+        debug_check_weight_from_error(&weight_from_error);
+        let belief = PoseBelief::new(max_particle_count, &*map);
         Self {
             max_particle_count,
             map,
@@ -150,6 +488,17 @@ where
             errors_from_lidar,
             errors_from_object,
             resampling_noise,
+            motion_limits: MotionLimits::default(),
+            update_period: 0.,
+            weights: Vec::new(),
+            stable_resampling: false,
+            rng: StdRng::from_entropy(),
+            kernel_width: 1.,
+            replace_zero_weights: false,
+            log_likelihood: false,
+            unique_ancestor_fraction: None,
+            effective_sample_fraction: None,
+            effective_sample_size: None,
             lidar_data_type: PhantomData,
             object_data_type: PhantomData,
         }
@@ -160,7 +509,7 @@ where
         max_particle_count: usize,
         weight_sum_threshold: f64,
         death_condition: DeathCondition,
-        map: Arc<Map2D>,
+        map: Arc<M>,
         weight_from_error: W,
         errors_from_lidar: L,
         errors_from_object: O,
@@ -170,6 +519,7 @@ where
         U: Distribution<V>,
         V: Into<f64>,
     {
+        debug_check_weight_from_error(&weight_from_error);
         let belief = PoseBelief::from_distributions(max_particle_count, belief_distr);
         Self {
             max_particle_count,
@@ -181,18 +531,118 @@ where
             errors_from_object,
             belief,
             resampling_noise,
+            motion_limits: MotionLimits::default(),
+            update_period: 0.,
+            weights: Vec::new(),
+            stable_resampling: false,
+            rng: StdRng::from_entropy(),
+            kernel_width: 1.,
+            replace_zero_weights: false,
+            log_likelihood: false,
+            unique_ancestor_fraction: None,
+            effective_sample_fraction: None,
+            effective_sample_size: None,
             lidar_data_type: PhantomData,
             object_data_type: PhantomData,
         }
     }
 
+    /// Sets the limits applied to control updates,
+    /// and the number of seconds between control updates which `control_update` clamps over
+    pub fn with_motion_limits(mut self, motion_limits: MotionLimits, update_period: f64) -> Self {
+        self.motion_limits = motion_limits;
+        self.update_period = update_period;
+        self
+    }
+
+    /// Sets whether particles which survive resampling keep their index in the belief
+    /// (e.g. so that visualizations coloring particles by index stay coherent)
+    pub fn with_stable_resampling(mut self, stable_resampling: bool) -> Self {
+        self.stable_resampling = stable_resampling;
+        self
+    }
+
+    /// Seeds the random number generator used to draw particles during resampling.
+    ///
+    /// Along with a resampling noise of `no_resampler` (or otherwise deterministic resampling noise),
+    /// this makes observation updates reproducible, e.g. when replaying a recorded run
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Sets whether resampling replaces particles with a weight of zero
+    /// with fresh uniformly distributed particles (see `observation_update`)
+    pub fn with_zero_weight_replacement(mut self, replace_zero_weights: bool) -> Self {
+        self.replace_zero_weights = replace_zero_weights;
+        self
+    }
+
+    /// Sets whether errors are negative log-likelihoods (e.g. from `lidar_likelihood_error`).
+    ///
+    /// The weight of a particle should then be the product of the likelihoods of its beams,
+    /// which is `exp_weight(E)` of the summed error, but that underflows to 0 for every particle
+    /// once scans have more than a few beams. Shifting the errors so that the most likely particle
+    /// has an error of 0 scales every weight by the same factor, so resampling is unchanged
+    /// and the most likely particle always has a weight of 1.
+    pub fn with_log_likelihood(mut self, log_likelihood: bool) -> Self {
+        self.log_likelihood = log_likelihood;
+        self
+    }
+
+    pub fn max_particle_count(&self) -> usize {
+        self.max_particle_count
+    }
+
+    /// Sets the max number of particles, which is used by future resampling and reinitialization
+    pub fn set_max_particle_count(&mut self, max_particle_count: usize) {
+        self.max_particle_count = max_particle_count;
+    }
+
+    pub fn kernel_width(&self) -> f64 {
+        self.kernel_width
+    }
+
+    /// Sets the width of the kernel which errors are divided by before they are weighted
+    pub fn set_kernel_width(&mut self, kernel_width: f64) {
+        self.kernel_width = kernel_width;
+    }
+
     /// Takes in a sensor which senses the total change in pose sensed since the last update
+    /// (see `control_update_with_dt`), which is clamped over `update_period` seconds
     pub fn control_update<U: Sensor<Output = Pose>>(&mut self, u: &U) {
-        let update = u.sense();
-        self.belief.iter_mut().for_each(|p| *p += update);
+        self.control_update_with_dt(u, self.update_period);
     }
 
-    /// Resamples the belief based on sensor data from `z`.
+    /// Takes in a sensor which senses the total change in pose over the last `delta_t` seconds
+    /// and clamps it to `motion_limits` (if any are set) before moving the belief,
+    /// forgetting the weights of the belief if any particles were dropped
+    ///
+    /// Updates which are not finite are skipped and particles which are not finite are dropped
+    pub fn control_update_with_dt<U: Sensor<Output = Pose>>(&mut self, u: &U, delta_t: f64) {
+        let update = self.motion_limits.limit(u.sense(), delta_t);
+        let len = self.belief.len();
+        PoseBelief::control_update(
+            &mut self.belief,
+            update,
+            self.max_particle_count,
+            &*self.map,
+        );
+        if self.belief.len() != len {
+            self.weights = Vec::new();
+        }
+    }
+
+    /// Resamples the belief based on sensor data from `y` and `z`.
+    ///
+    /// If every particle has an error of 0, the belief is resampled uniformly
+    /// and keeps its size (up to `max_particle_count`).
+    ///
+    /// Particles with a weight of exactly 0 (e.g. from a weight which underflows) can never be drawn,
+    /// so if most particles have a weight of 0 the resampled belief is made up of copies of the few others.
+    /// With `with_zero_weight_replacement`, the same fraction of the resampled belief as the fraction
+    /// of particles with a weight of 0 is instead given fresh uniformly distributed particles
+    /// (with a weight of 0) to keep the belief covering the map.
     ///
     /// Calculates error for each particle in parallel.
     pub fn observation_update(&mut self, y: &Y, z: &Z) {
@@ -204,50 +654,359 @@ where
                     + (&self.errors_from_object)(sample, z, &self.map)
             })
             .collect();
+        self.resample(errors);
+    }
+
+    /// Resamples the belief like `observation_update` but with the ranges of a scan predicted
+    /// for each particle elsewhere (e.g. raycast in bulk by an external tool), so no raycasting is done.
+    ///
+    /// `real` is the observed range of each beam and `predicted_per_particle` holds the predicted
+    /// range of each beam for each particle in the belief, in the same order as the belief.
+    /// The error of each particle is `scan_error` of the real and predicted ranges
+    /// (e.g. `range_scan_error`), and `errors_from_lidar` and `errors_from_object` are not used.
+    ///
+    /// Panics if there isn't a prediction for every particle in the belief.
+    pub fn observation_update_with_predictions<S>(
+        &mut self,
+        real: &[Option<f64>],
+        predicted_per_particle: &[Vec<Option<f64>>],
+        scan_error: S,
+    ) where
+        S: Fn(&[Option<f64>], &[Option<f64>]) -> f64 + Sync,
+    {
+        assert_eq!(
+            predicted_per_particle.len(),
+            self.belief.len(),
+            "Expected a predicted scan for every particle"
+        );
+        let errors: Vec<_> = predicted_per_particle
+            .par_iter()
+            .map(|predicted| scan_error(real, predicted))
+            .collect();
+        self.resample(errors);
+    }
+
+    /// Weights the belief from the `errors` of its particles and resamples it (see `observation_update`)
+    fn resample(&mut self, mut errors: Vec<f64>) {
+        if self.log_likelihood {
+            let min_error = errors.iter().cloned().fold(f64::INFINITY, f64::min);
+            if min_error.is_finite() {
+                errors.iter_mut().for_each(|error| *error -= min_error);
+            }
+        }
 
-        let mut new_particles = Vec::new();
-        let weights: Vec<f64> = if errors.iter().all(|error| error == &0.) {
+        // If there is no information from the sensors, every particle is weighted equally
+        // such that the weights of the belief sum to `weight_sum_threshold`,
+        // and resampling keeps the size of the belief (up to `max_particle_count`)
+        let uninformative = errors.iter().all(|error| error == &0.);
+        let weights: Vec<f64> = if uninformative {
             errors
                 .iter()
-                .map(|_| 2. * self.weight_sum_threshold / self.belief.len() as f64) // TODO: fixed parameter
+                .map(|_| self.weight_sum_threshold / self.belief.len() as f64)
                 .collect()
         } else {
             errors
                 .iter()
-                .map(|error| (self.weight_from_error)(error))
+                .map(|error| (self.weight_from_error)(&(error / self.kernel_width)))
                 .collect()
         };
         let distr = WeightedIndex::new(weights.clone()).unwrap();
-        let mut sum_weights = 0.;
-        let mut rng = thread_rng();
-        // TODO: rather than have max particle count and weight sum threshold parameters,
-        // it might be beneficial to use some dynamic combination of the two as the break condition.
-        while sum_weights < self.weight_sum_threshold
-            && new_particles.len() < self.max_particle_count
-        {
-            let idx = distr.sample(&mut rng);
-            sum_weights += weights[idx];
-            new_particles.push(self.belief[idx]);
+        let mut drawn = Vec::new();
+        if uninformative {
+            // the sum of the equal weights can fall just short of the threshold,
+            // so the size of the belief is drawn directly
+            let count = self.belief.len().min(self.max_particle_count);
+            drawn.extend((0..count).map(|_| distr.sample(&mut self.rng)));
+        } else {
+            let mut sum_weights = 0.;
+            // TODO: rather than have max particle count and weight sum threshold parameters,
+            // it might be beneficial to use some dynamic combination of the two as the break condition.
+            while sum_weights < self.weight_sum_threshold && drawn.len() < self.max_particle_count {
+                let idx = distr.sample(&mut self.rng);
+                sum_weights += weights[idx];
+                drawn.push(idx);
+            }
         }
+        if self.stable_resampling {
+            drawn = PoseBelief::stable_order(&drawn);
+        }
+        let mut new_weights: Vec<f64> = drawn.iter().map(|&idx| weights[idx]).collect();
+        let new_particles: Vec<Pose> = drawn.iter().map(|&idx| self.belief[idx]).collect();
         self.belief = if self.death_condition.triggered(&new_particles) {
-            PoseBelief::new(self.max_particle_count, self.map.size)
+            self.weights = Vec::new();
+            self.unique_ancestor_fraction = None;
+            self.effective_sample_fraction = None;
+            self.effective_sample_size = None;
+            PoseBelief::new(self.max_particle_count, &*self.map)
         } else {
-            new_particles
+            let mut ancestors = drawn.clone();
+            ancestors.sort_unstable();
+            ancestors.dedup();
+            self.unique_ancestor_fraction = Some(ancestors.len() as f64 / drawn.len() as f64);
+            let (weight_sum, weight_square_sum) =
+                weights.iter().fold((0., 0.), |(sum, square_sum), w| {
+                    (sum + w, square_sum + w * w)
+                });
+            let effective_sample_size = weight_sum * weight_sum / weight_square_sum;
+            self.effective_sample_size = Some(effective_sample_size);
+            self.effective_sample_fraction = Some(effective_sample_size / weights.len() as f64);
+            let mut new_belief: Vec<Pose> = new_particles
                 .iter()
                 .map(|&p| p + (self.resampling_noise)(self.belief.len()))
-                .collect()
+                .collect();
+            if self.replace_zero_weights {
+                // particles with a weight of 0 are never drawn, so the same fraction
+                // of the slots of the resampled belief are chosen at random to be replaced
+                let zero_count = weights.iter().filter(|&&weight| weight == 0.).count();
+                let replaced_count = zero_count * new_belief.len() / weights.len();
+                for i in rand::seq::index::sample(&mut self.rng, new_belief.len(), replaced_count) {
+                    new_belief[i] = PoseBelief::random_particle(&*self.map, &mut self.rng);
+                    new_weights[i] = 0.;
+                }
+            }
+            PoseBelief::normalize_weights(&mut new_weights);
+            self.weights = new_weights;
+            new_belief
+        };
+    }
+
+    /// The weights of the particles in the belief from the last observation update,
+    /// in the same order as the belief and normalized to sum to 1.
+    ///
+    /// Empty if there has not been an observation update since the belief was (re)initialized.
+    pub fn last_weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    /// Replaces the lowest weighted `replace_fraction` of the belief with `poses`
+    /// (e.g. candidate poses from another subsystem), keeping the rest of the belief.
+    ///
+    /// If more particles are replaced than there are `poses`, `poses` are repeated.
+    /// If there are no weights from the last observation update, the belief is weighted evenly.
+    /// Particles with a weight of NaN are replaced first.
+    /// Injected particles are given the highest weight of the belief (before the weights are renormalized).
+    pub fn inject_particles(&mut self, poses: &[Pose], replace_fraction: f64) {
+        if poses.is_empty() {
+            return;
+        }
+        let replace_count = ((replace_fraction.max(0.).min(1.) * self.belief.len() as f64).round()
+            as usize)
+            .min(self.belief.len());
+        if self.weights.len() != self.belief.len() {
+            self.weights = vec![1. / self.belief.len() as f64; self.belief.len()];
+        }
+        let weight = |i: usize| {
+            let weight = self.weights[i];
+            if weight.is_nan() {
+                f64::NEG_INFINITY
+            } else {
+                weight
+            }
         };
+        let mut order: Vec<usize> = (0..self.belief.len()).collect();
+        order.sort_by(|&a, &b| weight(a).total_cmp(&weight(b)));
+        let max_weight = self.weights.iter().cloned().fold(0., f64::max);
+        for (&idx, &pose) in order.iter().take(replace_count).zip(poses.iter().cycle()) {
+            self.belief[idx] = pose;
+            self.weights[idx] = max_weight;
+        }
+        PoseBelief::normalize_weights(&mut self.weights);
     }
 
     /// Finds the average of the belief
     pub fn get_prediction(&self) -> Pose {
-        let mut average_pose = Pose::default();
-        let mut angle = 0.;
-        for sample in &self.belief {
-            average_pose += *sample;
-            angle += sample.angle;
+        self.belief_summary().mean
+    }
+
+    /// Finds the covariance of the belief in the order angle, x, y
+    pub fn get_covariance(&self) -> [[f64; 3]; 3] {
+        self.belief_summary().covariance
+    }
+
+    /// Finds the entropy of a normal approximation of the belief
+    pub fn belief_entropy(&self) -> f64 {
+        self.belief_summary().entropy
+    }
+
+    /// Summarizes the belief and the weights from the last observation update in one pass.
+    ///
+    /// The effective sample size is that of the weights before the last resampling,
+    /// as resampled particles keep the weights of the particles they were drawn from
+    /// and would count a belief drawn from a single particle as fully effective.
+    pub fn belief_summary(&self) -> BeliefSummary {
+        let mut summary = BeliefSummary::new(&self.belief, &self.weights);
+        if let Some(effective_sample_size) = self.effective_sample_size {
+            summary.effective_sample_size = effective_sample_size;
+        }
+        summary
+    }
+
+    /// Finds the average of the belief if the root mean square distance of the particles
+    /// from the average position is at most `max_spread`, otherwise returns `None`
+    /// (e.g. before the belief has converged)
+    pub fn get_prediction_checked(&self, max_spread: f64) -> Option<Pose> {
+        if self.belief.is_empty() || PoseBelief::position_spread(&self.belief) > max_spread {
+            None
+        } else {
+            Some(self.get_prediction())
+        }
+    }
+
+    /// Detects when the belief has split into multiple hypotheses (e.g. in a symmetric map).
+    ///
+    /// The belief is clustered with `cluster_radius` (where a radian is worth `angle_weight`,
+    /// see `Pose::distance_to`) and the average poses of all clusters containing at least
+    /// `min_cluster_fraction` of the belief are returned, largest first,
+    /// if there is more than one. Otherwise returns `None`.
+    pub fn detect_ambiguity(
+        &self,
+        cluster_radius: f64,
+        angle_weight: f64,
+        min_cluster_fraction: f64,
+    ) -> Option<Vec<Pose>> {
+        let min_count = min_cluster_fraction * self.belief.len() as f64;
+        let hypotheses: Vec<Pose> =
+            PoseBelief::clusters(&self.belief, cluster_radius, angle_weight)
+                .into_iter()
+                .filter(|&(_, count)| count as f64 >= min_count)
+                .map(|(pose, _)| pose)
+                .collect();
+        if hypotheses.len() > 1 {
+            Some(hypotheses)
+        } else {
+            None
+        }
+    }
+
+    /// Creates a normalized 2D histogram of the positions of the particles in the belief
+    /// with `bins_x` by `bins_y` bins spanning the map, stored in row-major order
+    pub fn belief_histogram(&self, bins_x: usize, bins_y: usize) -> Vec<f64> {
+        PoseBelief::histogram(&self.belief, self.map.size(), bins_x, bins_y)
+    }
+
+    /// Checks the belief and the last resampling for common failure modes
+    /// (particle impoverishment, weight degeneracy, particles out of bounds and NaN contamination),
+    /// which usually come from tuning rather than bugs
+    pub fn health_report(&self) -> HealthReport {
+        let size = self.map.size();
+        let out_of_bounds_count = self
+            .belief
+            .iter()
+            .filter(|p| p.is_finite())
+            .filter(|p| {
+                p.position.x < 0.
+                    || p.position.y < 0.
+                    || p.position.x > size.x
+                    || p.position.y > size.y
+                    || self.map.contains(p.position)
+            })
+            .count();
+        let non_finite_count = self.belief.iter().filter(|p| !p.is_finite()).count()
+            + self.weights.iter().filter(|w| !w.is_finite()).count();
+        let impoverished = self
+            .unique_ancestor_fraction
+            .map_or(false, |f| f < MIN_UNIQUE_ANCESTOR_FRACTION);
+        let degenerate = self
+            .effective_sample_fraction
+            .map_or(false, |f| f.is_nan() || f < MIN_EFFECTIVE_SAMPLE_FRACTION);
+        let out_of_bounds =
+            out_of_bounds_count as f64 > MAX_OUT_OF_BOUNDS_FRACTION * self.belief.len() as f64;
+        let nan_contaminated = non_finite_count > 0;
+        let mut remedies = Vec::new();
+        if impoverished {
+            remedies.push(
+                "Few particles survived resampling: increase the resampling noise or the number of particles",
+            );
+        }
+        if degenerate {
+            remedies.push(
+                "Few particles had significant weight: widen the kernel (see `set_kernel_width`) or flatten `weight_from_error`",
+            );
+        }
+        if out_of_bounds {
+            remedies.push(
+                "Particles left the free space of the map: check the units and origin of the map and the motion sensor, or set motion limits",
+            );
+        }
+        if nan_contaminated {
+            remedies.push(
+                "Particles or weights are NaN or infinite: check the sensors and error calculators for non-finite outputs",
+            );
+        }
+        HealthReport {
+            unique_ancestor_fraction: self.unique_ancestor_fraction,
+            effective_sample_fraction: self.effective_sample_fraction,
+            out_of_bounds_count,
+            non_finite_count,
+            impoverished,
+            degenerate,
+            out_of_bounds,
+            nan_contaminated,
+            remedies,
+        }
+    }
+}
+
+impl<W, L, O, R, Y, Z> PoseMCL<W, L, O, R, Y, Z>
+where
+    W: WeightCalculator + Send + Sync,
+    L: ErrorCalculator<Y> + Send + Sync,
+    O: ErrorCalculator<Z> + Send + Sync,
+    R: ResampleNoiseCalculator + Send + Sync,
+    Y: Sync + Send,
+    Z: Sync + Send,
+{
+    /// Finds the line of the map closest to the prediction (e.g. for following a wall),
+    /// returning its index into `map.lines` and the distance of the prediction from it.
+    ///
+    /// The distance is positive if the prediction is to the left of the line
+    /// (looking from its first vertex to its second) and negative if it is to the right.
+    /// Returns `None` if the map has no lines.
+    pub fn nearest_wall(&self) -> Option<(usize, f64)> {
+        let position = self.get_prediction().position;
+        self.map.nearest_segment(position).map(|(line, _, dist)| {
+            let (start, end) = self.map.lines[line];
+            let (start, end) = (self.map.get_vertex(start), self.map.get_vertex(end));
+            let side = (end - start).cross_mag(position - start);
+            (line, if side < 0. { -dist } else { dist })
+        })
+    }
+}
+
+/// Clones the localizer, including its belief, with a freshly seeded random number generator
+/// so that the clone can be run independently (e.g. in a parameter sweep)
+impl<W, L, O, R, Y, Z, M> Clone for PoseMCL<W, L, O, R, Y, Z, M>
+where
+    W: WeightCalculator + Clone,
+    L: ErrorCalculator<Y, M> + Clone,
+    O: ErrorCalculator<Z, M> + Clone,
+    R: ResampleNoiseCalculator + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            belief: self.belief.clone(),
+            max_particle_count: self.max_particle_count,
+            weight_sum_threshold: self.weight_sum_threshold,
+            death_condition: self.death_condition,
+            weight_from_error: self.weight_from_error.clone(),
+            errors_from_lidar: self.errors_from_lidar.clone(),
+            errors_from_object: self.errors_from_object.clone(),
+            resampling_noise: self.resampling_noise.clone(),
+            motion_limits: self.motion_limits,
+            update_period: self.update_period,
+            weights: self.weights.clone(),
+            stable_resampling: self.stable_resampling,
+            rng: StdRng::from_entropy(),
+            kernel_width: self.kernel_width,
+            replace_zero_weights: self.replace_zero_weights,
+            log_likelihood: self.log_likelihood,
+            unique_ancestor_fraction: self.unique_ancestor_fraction,
+            effective_sample_fraction: self.effective_sample_fraction,
+            effective_sample_size: self.effective_sample_size,
+            lidar_data_type: PhantomData,
+            object_data_type: PhantomData,
         }
-        average_pose.with_angle(angle) / self.belief.len() as f64
     }
 }
 
@@ -269,6 +1028,9 @@ where
 /// `errors_from_sense` calculates the error of each particle from its sensor data
 ///
 /// `resampling_noise` calculates the amount of noise to add to each particle during resampling
+///
+/// `motion_limits` are the limits applied to control updates,
+/// which are `update_period` seconds apart unless given otherwise (see `control_update_with_dt`)
 pub struct KLDPoseMCL<W, E, R, Z>
 where
     W: WeightCalculator,
@@ -286,6 +1048,8 @@ where
     weight_from_error: W,
     errors_from_sense: E,
     resampling_noise: R,
+    motion_limits: MotionLimits,
+    update_period: f64,
     data_type: PhantomData<Z>,
 }
 
@@ -308,7 +1072,8 @@ where
         errors_from_sense: E,
         resampling_noise: R,
     ) -> Self {
-        let belief = PoseBelief::new(max_particle_count, map.size);
+        debug_check_weight_from_error(&weight_from_error);
+        let belief = PoseBelief::new(max_particle_count, &*map);
         Self {
             max_particle_count,
             map,
@@ -321,6 +1086,8 @@ where
             weight_from_error,
             errors_from_sense,
             resampling_noise,
+            motion_limits: MotionLimits::default(),
+            update_period: 0.,
             data_type: PhantomData,
         }
     }
@@ -342,6 +1109,7 @@ where
         U: Distribution<V>,
         V: Into<f64>,
     {
+        debug_check_weight_from_error(&weight_from_error);
         let belief = PoseBelief::from_distributions(max_particle_count, belief_distr);
         Self {
             max_particle_count,
@@ -355,25 +1123,74 @@ where
             weight_from_error,
             errors_from_sense,
             resampling_noise,
+            motion_limits: MotionLimits::default(),
+            update_period: 0.,
             data_type: PhantomData,
         }
     }
 
     /// Finds the average of the belief
     pub fn get_prediction(&self) -> Pose {
-        let mut average_pose = Pose::default();
-        let mut angle = 0.;
-        for sample in &self.belief {
-            average_pose += *sample;
-            angle += sample.angle;
+        self.belief_summary().mean
+    }
+
+    /// Finds the covariance of the belief in the order angle, x, y
+    pub fn get_covariance(&self) -> [[f64; 3]; 3] {
+        self.belief_summary().covariance
+    }
+
+    /// Finds the entropy of a normal approximation of the belief
+    pub fn belief_entropy(&self) -> f64 {
+        self.belief_summary().entropy
+    }
+
+    /// Summarizes the belief in one pass
+    pub fn belief_summary(&self) -> BeliefSummary {
+        BeliefSummary::from(&self.belief[..])
+    }
+
+    /// Finds the average of the belief if the root mean square distance of the particles
+    /// from the average position is at most `max_spread`, otherwise returns `None`
+    /// (e.g. before the belief has converged)
+    pub fn get_prediction_checked(&self, max_spread: f64) -> Option<Pose> {
+        if self.belief.is_empty() || PoseBelief::position_spread(&self.belief) > max_spread {
+            None
+        } else {
+            Some(self.get_prediction())
         }
-        average_pose.with_angle(angle) / self.belief.len() as f64
+    }
+
+    /// Creates a normalized 2D histogram of the positions of the particles in the belief
+    /// with `bins_x` by `bins_y` bins spanning the map, stored in row-major order
+    pub fn belief_histogram(&self, bins_x: usize, bins_y: usize) -> Vec<f64> {
+        PoseBelief::histogram(&self.belief, self.map.size, bins_x, bins_y)
+    }
+
+    /// Sets the limits applied to control updates,
+    /// and the number of seconds between control updates which `control_update` clamps over
+    pub fn with_motion_limits(mut self, motion_limits: MotionLimits, update_period: f64) -> Self {
+        self.motion_limits = motion_limits;
+        self.update_period = update_period;
+        self
     }
 
     /// Takes in a sensor which senses the total change in pose sensed since the last update
+    /// (see `control_update_with_dt`), which is clamped over `update_period` seconds
     pub fn control_update<U: Sensor<Output = Pose>>(&mut self, u: &U) {
-        let update = u.sense();
-        self.belief.iter_mut().for_each(|p| *p += update);
+        self.control_update_with_dt(u, self.update_period);
+    }
+
+    /// Takes in a sensor which senses the total change in pose over the last `delta_t` seconds
+    /// and clamps it to `motion_limits` (if any are set) before moving the belief
+    ///
+    /// Updates which are not finite are skipped and particles which are not finite are dropped
+    pub fn control_update_with_dt<U: Sensor<Output = Pose>>(&mut self, u: &U, delta_t: f64) {
+        PoseBelief::control_update(
+            &mut self.belief,
+            self.motion_limits.limit(u.sense(), delta_t),
+            self.max_particle_count,
+            &*self.map,
+        );
     }
 
     /// Resamples the belief based on sensor data from `z`.
@@ -423,7 +1240,7 @@ where
         }
         // Check whether or not to restart the algorithm based on death_condition
         self.belief = if self.death_condition.triggered(&new_particles) {
-            PoseBelief::new(self.max_particle_count, self.map.size)
+            PoseBelief::new(self.max_particle_count, &*self.map)
         } else {
             new_particles
                 .iter()
@@ -432,3 +1249,65 @@ where
         };
     }
 }
+
+impl<W, E, R, Z> Clone for KLDPoseMCL<W, E, R, Z>
+where
+    W: WeightCalculator + Clone,
+    E: ErrorCalculator<Z> + Clone,
+    R: ResampleNoiseCalculator + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            belief: self.belief.clone(),
+            max_particle_count: self.max_particle_count,
+            min_particle_count: self.min_particle_count,
+            error_bound: self.error_bound,
+            error_confidence: self.error_confidence,
+            bin_size: self.bin_size,
+            death_condition: self.death_condition,
+            weight_from_error: self.weight_from_error.clone(),
+            errors_from_sense: self.errors_from_sense.clone(),
+            resampling_noise: self.resampling_noise.clone(),
+            motion_limits: self.motion_limits,
+            update_period: self.update_period,
+            data_type: PhantomData,
+        }
+    }
+}
+
+impl<W, L, O, R, Y, Z> Localizer for PoseMCL<W, L, O, R, Y, Z>
+where
+    W: WeightCalculator + Send + Sync,
+    L: ErrorCalculator<Y> + Send + Sync,
+    O: ErrorCalculator<Z> + Send + Sync,
+    R: ResampleNoiseCalculator + Send + Sync,
+    Y: Sync + Send,
+    Z: Sync + Send,
+{
+    fn prediction(&self) -> Pose {
+        self.get_prediction()
+    }
+
+    fn covariance(&self) -> Matrix3<f64> {
+        let covariance = self.get_covariance();
+        Matrix3::from_fn(|row, col| covariance[row][col])
+    }
+}
+
+impl<W, E, R, Z> Localizer for KLDPoseMCL<W, E, R, Z>
+where
+    W: WeightCalculator + Send + Sync,
+    E: ErrorCalculator<Z> + Send + Sync,
+    R: ResampleNoiseCalculator + Send + Sync,
+    Z: Sync + Send,
+{
+    fn prediction(&self) -> Pose {
+        self.get_prediction()
+    }
+
+    fn covariance(&self) -> Matrix3<f64> {
+        let covariance = self.get_covariance();
+        Matrix3::from_fn(|row, col| covariance[row][col])
+    }
+}
@@ -1,3 +1,4 @@
+pub mod health;
 pub mod kalman_filter;
 pub mod localization;
 pub mod presets;
@@ -1,20 +1,25 @@
 use crate::{
     ai::localization::{ErrorCalculator, ResampleNoiseCalculator, WeightCalculator},
-    map::Map2D,
-    sensors::{LimitedSensor, Sensor},
+    map::{Map2D, Raycaster, RegionId},
+    sensors::{LimitedSensor, MaskedSensor, Sensor},
     utility::{Point, Pose, Pose3D},
 };
+use nalgebra::{DMatrix, DVector};
 use rand::prelude::*;
 use rand_distr::Normal;
 use rayon::prelude::*;
 use std::{
+    collections::HashMap,
     f64::{consts::PI, INFINITY},
     ops::Range,
     sync::Arc,
 };
 
 /// Creates a `ResampleNoiseCalculator` which produces uniform noise within the range ±`angle_margin` ±`position_margin`
-pub fn uniform_resampler(angle_margin: f64, position_margin: f64) -> impl ResampleNoiseCalculator {
+pub fn uniform_resampler(
+    angle_margin: f64,
+    position_margin: f64,
+) -> impl ResampleNoiseCalculator + Clone {
     move |_| {
         Pose::random_from_range(Pose {
             angle: angle_margin,
@@ -23,8 +28,13 @@ pub fn uniform_resampler(angle_margin: f64, position_margin: f64) -> impl Resamp
     }
 }
 
+/// Creates a `ResampleNoiseCalculator` which produces no noise, so resampled particles are exact copies of their parents
+pub fn no_resampler() -> impl ResampleNoiseCalculator + Clone {
+    |_| Pose::default()
+}
+
 /// Creates a `ResmapleNoiseCalculator` which produces noise normally distributed with the standard deviations provided
-pub fn normal_resampler(angle_dev: f64, position_dev: f64) -> impl ResampleNoiseCalculator {
+pub fn normal_resampler(angle_dev: f64, position_dev: f64) -> impl ResampleNoiseCalculator + Clone {
     let angle_dist = Normal::new(0., angle_dev).unwrap();
     let pos_dist = Normal::new(0., position_dev).unwrap();
     move |_| {
@@ -37,7 +47,7 @@ pub fn normal_resampler(angle_dev: f64, position_dev: f64) -> impl ResampleNoise
 }
 
 /// Creates a `WeightCalculator` which takes `base` to the power of negative error
-pub fn exp_weight(base: f64) -> impl WeightCalculator {
+pub fn exp_weight(base: f64) -> impl WeightCalculator + Clone {
     move |error: &f64| base.powf(-error)
 }
 
@@ -53,38 +63,272 @@ pub fn exp_weight(base: f64) -> impl WeightCalculator {
 /// The second part of the error is the number of predicted scan points that did not exist put to the power of `discrepancy_pow`.
 ///
 /// Total error is the first part plus the second part multiplied by `error_scale`
-pub fn lidar_error<S>(discrepancy_pow: f64, error_scale: f64) -> impl ErrorCalculator<S>
+///
+/// Predicted ranges come from `Raycaster::raycast_distance`, so any map representation can be used.
+/// An infinite predicted range (e.g. on an open world map) is treated as no range.
+pub fn lidar_error<S, M>(
+    discrepancy_pow: f64,
+    error_scale: f64,
+) -> impl ErrorCalculator<S, M> + Clone
+where
+    S: Sensor<Output = Vec<Point>> + LimitedSensor<Range<f64>>,
+    M: Raycaster + Send + Sync,
+{
+    move |&sample: &Pose, lidar: &S, map: &Arc<M>| -> f64 {
+        scan_error(
+            sample,
+            lidar,
+            map,
+            |_| false,
+            |_| 1.,
+            discrepancy_pow,
+            error_scale,
+        )
+    }
+}
+
+/// Creates an `ErrorCalculator` like `lidar_error` for a map with labeled regions (see `Map2D::with_label`)
+///
+/// The difference between the observed and predicted scan point of each beam
+/// predicted to hit a line labeled with a region in `reliability` is multiplied by its reliability,
+/// so beams hitting unreliable surfaces (e.g. glass which the lidar may see through) contribute less error.
+/// Beams hitting unlabeled lines or regions missing from `reliability` have a reliability of 1.
+pub fn labeled_lidar_error<S, M>(
+    discrepancy_pow: f64,
+    error_scale: f64,
+    reliability: HashMap<RegionId, f64>,
+) -> impl ErrorCalculator<S, M> + Clone
+where
+    S: Sensor<Output = Vec<Point>> + LimitedSensor<Range<f64>>,
+    M: Raycaster + Send + Sync,
+{
+    move |&sample: &Pose, lidar: &S, map: &Arc<M>| -> f64 {
+        scan_error(
+            sample,
+            lidar,
+            map,
+            |_| false,
+            |label| {
+                label
+                    .and_then(|label| reliability.get(&label).copied())
+                    .unwrap_or(1.)
+            },
+            discrepancy_pow,
+            error_scale,
+        )
+    }
+}
+
+/// Creates an `ErrorCalculator` like `lidar_error` for a lidar wrapped in a `MaskedSensor`
+///
+/// Scan points which would fall in a masked region given the pose of the sample
+/// (e.g. beams hitting another robot) contribute no error and are not counted.
+pub fn masked_lidar_error<S, M>(
+    discrepancy_pow: f64,
+    error_scale: f64,
+) -> impl ErrorCalculator<MaskedSensor<S>, M> + Clone
+where
+    S: Sensor<Output = Vec<Point>> + LimitedSensor<Range<f64>>,
+    M: Raycaster + Send + Sync,
+{
+    move |&sample: &Pose, lidar: &MaskedSensor<S>, map: &Arc<M>| -> f64 {
+        scan_error(
+            sample,
+            lidar,
+            map,
+            |point| lidar.is_masked(point),
+            |_| 1.,
+            discrepancy_pow,
+            error_scale,
+        )
+    }
+}
+
+/// The error of `lidar_error`, skipping scan points whose world coordinates are `masked`
+/// and weighting the difference of each beam by the `reliability` of the label of the line it hits
+fn scan_error<S, M, K, R>(
+    sample: Pose,
+    lidar: &S,
+    map: &Arc<M>,
+    masked: K,
+    reliability: R,
+    discrepancy_pow: f64,
+    error_scale: f64,
+) -> f64
+where
+    S: Sensor<Output = Vec<Point>> + LimitedSensor<Range<f64>>,
+    M: Raycaster + Send + Sync,
+    K: Fn(Point) -> bool,
+    R: Fn(Option<RegionId>) -> f64 + Sync,
+{
+    let sample = sample + lidar.relative_pose();
+    let lidar_scan: Vec<Point> = lidar
+        .sense()
+        .into_iter()
+        .filter(|scan_point| {
+            !masked(
+                sample.position + Point::polar(sample.angle + scan_point.angle(), scan_point.mag()),
+            )
+        })
+        .collect();
+    if lidar_scan.is_empty() {
+        return 0.;
+    }
+    let len = lidar_scan.len() as f64;
+    let lidar_range = lidar.range().unwrap_or(0.0..INFINITY);
+    let error: (f64, f64) = lidar_scan // TODO: this parallelization could be better ith
+        .par_iter()
+        .map(|scan_point| {
+            match map.raycast_distance_labeled(
+                sample
+                    + Pose {
+                        angle: scan_point.angle(),
+                        ..Pose::default()
+                    },
+            ) {
+                (Some(dist), label) if lidar_range.contains(&dist) => {
+                    (reliability(label) * (scan_point.mag() - dist).abs(), 0.)
+                }
+                _ => (0., 1.),
+            }
+        })
+        .reduce(|| (0., 0.), |a, b| (a.0 + b.0, a.1 + b.1));
+    error_scale * (error.0 + error.1.powf(discrepancy_pow)) / len
+}
+
+/// Creates a function giving the error of `lidar_error` from the observed range of each beam of a scan
+/// and the range of each beam predicted for a particle, for `PoseMCL::observation_update_with_predictions`
+///
+/// Beams which were not observed are skipped, and observed beams without a predicted range
+/// (e.g. beyond the range of the lidar) count as discrepancies. An infinite range
+/// (e.g. from `Raycaster::raycast_distance` on an open world map) is treated as no range,
+/// like a real beam which got no return.
+pub fn range_scan_error(
+    discrepancy_pow: f64,
+    error_scale: f64,
+) -> impl Fn(&[Option<f64>], &[Option<f64>]) -> f64 + Clone {
+    move |real: &[Option<f64>], predicted: &[Option<f64>]| -> f64 {
+        let mut len = 0;
+        let mut error: (f64, f64) = (0., 0.);
+        for (real, predicted) in real.iter().zip(predicted) {
+            if let Some(real) = real.filter(|real| real.is_finite()) {
+                len += 1;
+                match predicted.filter(|predicted| predicted.is_finite()) {
+                    Some(predicted) => error.0 += (real - predicted).abs(),
+                    None => error.1 += 1.,
+                }
+            }
+        }
+        if len == 0 {
+            return 0.;
+        }
+        error_scale * (error.0 + error.1.powf(discrepancy_pow)) / len as f64
+    }
+}
+
+/// Creates an `ErrorCalculator` for a lidar which gives the negative log-likelihood of the observed scan,
+/// to be used with `PoseMCL::with_log_likelihood` and `exp_weight(E)`.
+///
+/// `lidar_error` sums the absolute differences of the beams and weights the sum with one kernel,
+/// which isn't a probability: it ranks a particle with one badly wrong beam above a particle
+/// with every beam slightly off if the total difference is smaller. Treating the beams as independent,
+/// the probability of the scan is the product of the likelihoods of its beams, `∏ p(zᵢ | x)`,
+/// so the error is the sum of their negative log-likelihoods (which doesn't underflow).
+///
+/// The likelihood of a beam is a normal distribution with a standard deviation of `range_dev`
+/// around the predicted range, or `miss_likelihood` if no scan point was predicted to exist.
+pub fn lidar_likelihood_error<S, M>(
+    range_dev: f64,
+    miss_likelihood: f64,
+) -> impl ErrorCalculator<S, M> + Clone
 where
     S: Sensor<Output = Vec<Point>> + LimitedSensor<Range<f64>>,
+    M: Raycaster + Send + Sync,
 {
-    move |&sample: &Pose, lidar: &S, map: &Arc<Map2D>| -> f64 {
+    let log_normalization = (range_dev * (2. * PI).sqrt()).ln();
+    move |&sample: &Pose, lidar: &S, map: &Arc<M>| -> f64 {
+        let sample = sample + lidar.relative_pose();
+        let lidar_range = lidar.range().unwrap_or(0.0..INFINITY);
+        lidar
+            .sense()
+            .par_iter()
+            .map(|scan_point| {
+                match map.raycast_distance(
+                    sample
+                        + Pose {
+                            angle: scan_point.angle(),
+                            ..Pose::default()
+                        },
+                ) {
+                    Some(dist) if lidar_range.contains(&dist) => {
+                        let diff = scan_point.mag() - dist;
+                        diff.powi(2) / (2. * range_dev.powi(2)) + log_normalization
+                    }
+                    _ => -miss_likelihood.ln(),
+                }
+            })
+            .sum()
+    }
+}
+
+/// Creates an `ErrorCalculator` for a lidar whose beams have correlated noise,
+/// where `covariance` is the covariance of the range errors of the beams of a scan, in scan order.
+///
+/// The first part of the error is the squared Mahalanobis distance of the vector of differences between
+/// the observed and predicted scan points. Scan points which were not predicted to exist have a difference of 0.
+///
+/// The second part of the error is the number of predicted scan points that did not exist put to the power of `discrepancy_pow`.
+///
+/// Total error is the sum of both parts multiplied by `error_scale` divided by the number of scan points.
+///
+/// If the length of a scan does not match the size of `covariance`, the beams are treated as independent
+/// with the mean variance of `covariance`.
+///
+/// Panics if `covariance` is not invertible.
+pub fn correlated_lidar_error<S, M>(
+    covariance: DMatrix<f64>,
+    discrepancy_pow: f64,
+    error_scale: f64,
+) -> impl ErrorCalculator<S, M> + Clone
+where
+    S: Sensor<Output = Vec<Point>> + LimitedSensor<Range<f64>>,
+    M: Raycaster + Send + Sync,
+{
+    let mean_variance = covariance.trace() / covariance.nrows() as f64;
+    let inverse = covariance
+        .try_inverse()
+        .expect("Beam covariance matrix is not invertible");
+    move |&sample: &Pose, lidar: &S, map: &Arc<M>| -> f64 {
         let sample = sample + lidar.relative_pose();
         let lidar_scan = lidar.sense();
+        if lidar_scan.is_empty() {
+            return 0.;
+        }
         let len = lidar_scan.len() as f64;
         let lidar_range = lidar.range().unwrap_or(0.0..INFINITY);
-        let error: (f64, f64) = lidar_scan // TODO: this parallelization could be better ith
+        let beam_errors: Vec<Option<f64>> = lidar_scan
             .par_iter()
             .map(|scan_point| {
-                match map.raycast(
+                match map.raycast_distance(
                     sample
                         + Pose {
                             angle: scan_point.angle(),
                             ..Pose::default()
                         },
                 ) {
-                    Some(predicted_point)
-                        if lidar_range.contains(&predicted_point.dist(sample.position)) =>
-                    {
-                        (
-                            (scan_point.mag() - predicted_point.dist(sample.position)).abs(),
-                            0.,
-                        )
-                    }
-                    _ => (0., 1.),
+                    Some(dist) if lidar_range.contains(&dist) => Some(scan_point.mag() - dist),
+                    _ => None,
                 }
             })
-            .reduce(|| (0., 0.), |a, b| (a.0 + b.0, a.1 + b.1));
-        error_scale * (error.0 + error.1.powf(discrepancy_pow)) / len
+            .collect();
+        let discrepancies = beam_errors.iter().filter(|e| e.is_none()).count() as f64;
+        let beam_errors = DVector::from_vec(beam_errors.iter().map(|e| e.unwrap_or(0.)).collect());
+        let mahalanobis_sq = if inverse.nrows() == lidar_scan.len() {
+            (&inverse * &beam_errors).dot(&beam_errors)
+        } else {
+            beam_errors.dot(&beam_errors) / mean_variance
+        };
+        error_scale * (mahalanobis_sq + discrepancies.powf(discrepancy_pow)) / len
     }
 }
 
@@ -99,7 +343,7 @@ where
 pub fn object_detection_error<S>(
     discrepancy_factor: f64,
     error_scale: f64,
-) -> impl ErrorCalculator<S>
+) -> impl ErrorCalculator<S> + Clone
 where
     S: Sensor<Output = Vec<Point>> + LimitedSensor<(f64, f64)>,
 {
@@ -113,35 +357,85 @@ where
             fov = 2. * PI;
             max_dist = None;
         }
+        detection_error(
+            sample,
+            object_detector,
+            map,
+            fov,
+            max_dist,
+            discrepancy_factor,
+            error_scale,
+        )
+    }
+}
 
-        let mut sum_error = 0.;
-        let mut pred_observation: Vec<Point> = map
-            .cull_points(
-                sample + object_detector.relative_pose(),
-                Point { x: fov, y: 2. * PI },
-                max_dist,
-            )
-            .iter()
-            .map(|elem| elem.position.clone().without_z())
-            .collect();
-        let mut observation = object_detector.sense();
-        observation.sort_by(|a, b| a.mag().partial_cmp(&b.mag()).unwrap());
-        pred_observation.sort_by(|a, b| a.mag().partial_cmp(&b.mag()).unwrap());
-        // TODO: This method of calculating error is not entirely sound
-        for (&real, &pred) in observation.iter().zip(pred_observation.iter()) {
-            sum_error += (real - pred).mag();
-        }
-        sum_error +=
-            discrepancy_factor * (observation.len() as f64 - pred_observation.len() as f64).abs();
-        sum_error * error_scale
+/// Creates an `ErrorCalculator` like `object_detection_error` for a sensor which senses the landmarks
+/// (the targets of the map) within a nominal `fov` and `max_dist`, rather than the range of the sensor.
+///
+/// The predicted observation of each sample is culled with the same geometry as the real sensor,
+/// so landmarks outside of `fov` or farther than `max_dist` are not expected to be seen.
+pub fn landmark_error<S>(
+    fov: f64,
+    max_dist: Option<f64>,
+    discrepancy_factor: f64,
+    error_scale: f64,
+) -> impl ErrorCalculator<S> + Clone
+where
+    S: Sensor<Output = Vec<Point>>,
+{
+    move |&sample: &Pose, object_detector: &S, map: &Arc<Map2D>| {
+        detection_error(
+            sample,
+            object_detector,
+            map,
+            fov,
+            max_dist,
+            discrepancy_factor,
+            error_scale,
+        )
+    }
+}
+
+/// The error of `object_detection_error` for a sensor with `fov` and `max_dist`
+fn detection_error<S>(
+    sample: Pose,
+    object_detector: &S,
+    map: &Arc<Map2D>,
+    fov: f64,
+    max_dist: Option<f64>,
+    discrepancy_factor: f64,
+    error_scale: f64,
+) -> f64
+where
+    S: Sensor<Output = Vec<Point>>,
+{
+    let mut sum_error = 0.;
+    let mut pred_observation: Vec<Point> = map
+        .cull_points(
+            sample + object_detector.relative_pose(),
+            Point { x: fov, y: 2. * PI },
+            max_dist,
+        )
+        .iter()
+        .map(|elem| elem.position.clone().without_z())
+        .collect();
+    let mut observation = object_detector.sense();
+    observation.sort_by(|a, b| a.mag().partial_cmp(&b.mag()).unwrap());
+    pred_observation.sort_by(|a, b| a.mag().partial_cmp(&b.mag()).unwrap());
+    // TODO: This method of calculating error is not entirely sound
+    for (&real, &pred) in observation.iter().zip(pred_observation.iter()) {
+        sum_error += (real - pred).mag();
     }
+    sum_error +=
+        discrepancy_factor * (observation.len() as f64 - pred_observation.len() as f64).abs();
+    sum_error * error_scale
 }
 
 pub fn object_3d_detection_error<S>(
     discrepancy_factor: f64,
     angle_factor: f64,
     error_scale: f64,
-) -> impl ErrorCalculator<S>
+) -> impl ErrorCalculator<S> + Clone
 where
     S: Sensor<Output = Vec<Pose3D>> + LimitedSensor<(Point, f64)>,
 {
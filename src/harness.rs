@@ -0,0 +1,221 @@
+use crate::{
+    ai::{
+        kalman_filter::{KalmanFilter, LocalizationFilter},
+        localization::{ErrorCalculator, PoseMCL, ResampleNoiseCalculator, WeightCalculator},
+    },
+    sensors::{
+        dummy::{DummyLidar, DummyPositionSensor},
+        Sensor,
+    },
+    utility::Pose,
+};
+use nalgebra::{Matrix6, RowVector6};
+
+/// Whether `prediction` is within `pos_thresh` distance and `angle_thresh` radians of `true_pose`
+pub fn is_converged(true_pose: Pose, prediction: Pose, pos_thresh: f64, angle_thresh: f64) -> bool {
+    true_pose.position.dist(prediction.position) <= pos_thresh
+        && true_pose.angle_dist(prediction) <= angle_thresh
+}
+
+/// Root mean squared distance between the true and predicted positions of the results of `Simulation::run`
+pub fn position_rmse(results: &[(Pose, Pose)]) -> f64 {
+    let squared_error: f64 = results
+        .iter()
+        .map(|(true_pose, prediction)| true_pose.position.dist(prediction.position).powi(2))
+        .sum();
+    (squared_error / results.len() as f64).sqrt()
+}
+
+/// Runs the simulation built by `simulation` for each of `configs` for `steps` steps,
+/// returning each configuration with the position RMSE of its run (see `position_rmse`).
+///
+/// Every localizer is seeded with `seed` (see `PoseMCL::with_seed`) so that configurations
+/// are compared on the same resampling draws. Sensor noise and the initial belief are not seeded.
+pub fn sweep<C, S, T, W, L, O, R>(
+    configs: impl IntoIterator<Item = C>,
+    simulation: S,
+    steps: usize,
+    seed: u64,
+) -> Vec<(C, f64)>
+where
+    S: Fn(&C) -> Simulation<T, W, L, O, R>,
+    T: Fn(f64) -> Pose,
+    W: WeightCalculator + Send + Sync,
+    L: ErrorCalculator<DummyLidar> + Send + Sync,
+    O: ErrorCalculator<()> + Send + Sync,
+    R: ResampleNoiseCalculator + Send + Sync,
+{
+    configs
+        .into_iter()
+        .map(|config| {
+            let mut sim = simulation(&config);
+            sim.localizer = sim.localizer.with_seed(seed);
+            let rmse = position_rmse(&sim.run(steps));
+            (config, rmse)
+        })
+        .collect()
+}
+
+/// A simulation which advances a true trajectory, dummy sensors following it
+/// and a localizer using those sensors together.
+///
+/// `trajectory` gives the true pose of the robot at a time in seconds
+///
+/// `delta_t` is the number of seconds simulated by each step,
+/// or by the latest step if the steps follow a schedule (see `Simulation::with_dt_schedule`)
+///
+/// `lidar` is updated with every step regardless of its period
+///
+/// `filter` optionally fuses the prediction of `localizer` with the sensed velocity
+/// (see `Simulation::with_filter`)
+pub struct Simulation<T, W, L, O, R>
+where
+    T: Fn(f64) -> Pose,
+    W: WeightCalculator,
+    L: ErrorCalculator<DummyLidar>,
+    O: ErrorCalculator<()>,
+    R: ResampleNoiseCalculator,
+{
+    pub trajectory: T,
+    pub delta_t: f64,
+    pub lidar: DummyLidar,
+    pub motion_sensor: DummyPositionSensor,
+    pub localizer: PoseMCL<W, L, O, R, DummyLidar, ()>,
+    time: f64,
+    dt_schedule: Option<Box<dyn FnMut() -> f64>>,
+    filter: Option<(LocalizationFilter, Matrix6<f64>, Matrix6<f64>)>,
+}
+
+impl<T, W, L, O, R> Simulation<T, W, L, O, R>
+where
+    T: Fn(f64) -> Pose,
+    W: WeightCalculator + Send + Sync,
+    L: ErrorCalculator<DummyLidar> + Send + Sync,
+    O: ErrorCalculator<()> + Send + Sync,
+    R: ResampleNoiseCalculator + Send + Sync,
+{
+    /// Creates a new simulation starting at time 0 with the sensors at the start of `trajectory`
+    pub fn new(
+        trajectory: T,
+        delta_t: f64,
+        mut lidar: DummyLidar,
+        mut motion_sensor: DummyPositionSensor,
+        localizer: PoseMCL<W, L, O, R, DummyLidar, ()>,
+    ) -> Self {
+        let start = trajectory(0.);
+        lidar.update_pose(start);
+        // updated twice so that no motion is sensed before the first step
+        motion_sensor.update_pose(start);
+        motion_sensor.update_pose(start);
+        motion_sensor.set_delta_t(delta_t);
+        Self {
+            trajectory,
+            delta_t,
+            lidar,
+            motion_sensor,
+            localizer,
+            time: 0.,
+            dt_schedule: None,
+            filter: None,
+        }
+    }
+
+    /// Steps the simulation by the number of seconds returned by `dt_schedule` for each step
+    /// instead of a fixed `delta_t`, e.g. to test robustness to jittery timing.
+    ///
+    /// An iterator of time deltas can be used with `move || dts.next().unwrap()`.
+    pub fn with_dt_schedule(mut self, dt_schedule: impl FnMut() -> f64 + 'static) -> Self {
+        self.dt_schedule = Some(Box::new(dt_schedule));
+        self
+    }
+
+    /// Runs `filter` alongside the localizer, with a prediction update by the time delta of each step
+    /// followed by a measurement update with the prediction of the localizer and the sensed velocity.
+    ///
+    /// Nothing senses acceleration, so predictions assume a constant velocity with the
+    /// covariance `process_noise` per second squared. `measurement_noise` is the covariance
+    /// of the measurements in the order angle, x, y, angular velocity, x velocity, y velocity.
+    pub fn with_filter(
+        mut self,
+        filter: LocalizationFilter,
+        process_noise: Matrix6<f64>,
+        measurement_noise: Matrix6<f64>,
+    ) -> Self {
+        self.filter = Some((filter, process_noise, measurement_noise));
+        self
+    }
+
+    /// The filter run alongside the localizer, if any (see `Simulation::with_filter`)
+    pub fn filter(&self) -> Option<&LocalizationFilter> {
+        self.filter.as_ref().map(|(filter, _, _)| filter)
+    }
+
+    /// Number of seconds simulated so far
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Moves the robot along the trajectory by `delta_t` (or the next time delta of the schedule),
+    /// updates the sensors and runs a control and observation update on the localizer
+    /// (and a prediction and measurement update on the filter, if any).
+    ///
+    /// Returns the true pose of the robot and the prediction of the localizer
+    pub fn step(&mut self) -> (Pose, Pose) {
+        if let Some(dt_schedule) = &mut self.dt_schedule {
+            self.delta_t = dt_schedule();
+            self.motion_sensor.set_delta_t(self.delta_t);
+        }
+        self.time += self.delta_t;
+        let true_pose = (self.trajectory)(self.time);
+        self.motion_sensor.update_pose(true_pose);
+        self.lidar.update_pose(true_pose);
+        self.lidar.update_with_maps(vec![]);
+        self.localizer
+            .control_update_with_dt(&self.motion_sensor, self.delta_t);
+        self.localizer.observation_update(&self.lidar, &());
+        let prediction = self.localizer.get_prediction();
+        if let Some((filter, process_noise, measurement_noise)) = &mut self.filter {
+            filter.prediction_update(
+                self.delta_t,
+                vec![0.; 3],
+                self.delta_t.powi(2) * *process_noise,
+            );
+            let velocity = self.motion_sensor.sense() / self.delta_t;
+            if let Err(e) = filter.measurement_update(
+                RowVector6::from_vec(vec![
+                    prediction.angle,
+                    prediction.position.x,
+                    prediction.position.y,
+                    velocity.angle,
+                    velocity.position.x,
+                    velocity.position.y,
+                ]),
+                *measurement_noise,
+            ) {
+                println!("Skipping measurement update: {}", e);
+            }
+        }
+        (true_pose, prediction)
+    }
+
+    /// Runs `steps` steps, returning the result of each
+    pub fn run(&mut self, steps: usize) -> Vec<(Pose, Pose)> {
+        (0..steps).map(|_| self.step()).collect()
+    }
+
+    /// Steps the simulation until the prediction of the localizer first converges
+    /// to the true pose (see `is_converged`), for comparing configurations.
+    ///
+    /// Returns the number of steps taken, or `None` if it did not converge within `max_steps`
+    pub fn steps_to_converge(
+        &mut self,
+        pos_thresh: f64,
+        angle_thresh: f64,
+        max_steps: usize,
+    ) -> Option<usize> {
+        (1..=max_steps).find(|_| {
+            let (true_pose, prediction) = self.step();
+            is_converged(true_pose, prediction, pos_thresh, angle_thresh)
+        })
+    }
+}
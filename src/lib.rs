@@ -1,4 +1,5 @@
 pub mod ai;
+pub mod harness;
 pub mod map;
 pub mod replay;
 pub mod sensors;
@@ -6,6 +7,60 @@ pub mod utility;
 
 #[cfg(test)]
 mod tests {
+    use super::{
+        ai::localization::{
+            DeathCondition, ErrorCalculator, PoseMCL, ResampleNoiseCalculator, WeightCalculator,
+        },
+        map::{Map2D, Object2D},
+        utility::{Point, Pose},
+    };
+    use std::sync::Arc;
+
+    /// A 10 by 10 room
+    fn square_room() -> Arc<Map2D> {
+        Arc::new(Map2D::new(vec![Object2D::Rectangle(
+            Point { x: 0., y: 0. },
+            Point { x: 10., y: 10. },
+        )]))
+    }
+
+    /// An error calculator for a sensor which is ignored
+    fn no_error<Z>(_: &Pose, _: &Z, _: &Arc<Map2D>) -> f64 {
+        0.
+    }
+
+    type NoObjectError = fn(&Pose, &(), &Arc<Map2D>) -> f64;
+
+    /// A `PoseMCL` on `map` which never restarts and ignores its object sensor
+    fn new_test_mcl<W, L, R, Y>(
+        map: Arc<Map2D>,
+        max_particle_count: usize,
+        weight_sum_threshold: f64,
+        weight_from_error: W,
+        errors_from_lidar: L,
+        resampling_noise: R,
+    ) -> PoseMCL<W, L, NoObjectError, R, Y, ()>
+    where
+        W: WeightCalculator + Send + Sync,
+        L: ErrorCalculator<Y> + Send + Sync,
+        R: ResampleNoiseCalculator + Send + Sync,
+        Y: Send + Sync,
+    {
+        PoseMCL::new(
+            max_particle_count,
+            weight_sum_threshold,
+            DeathCondition {
+                particle_count_threshold: usize::MAX,
+                particle_concentration_threshold: 0.,
+            },
+            map,
+            weight_from_error,
+            errors_from_lidar,
+            no_error,
+            resampling_noise,
+        )
+    }
+
     #[test]
     fn test_dummy_sensor() {
         use super::sensors::{dummy::DummySensor, *};
@@ -223,4 +278,2881 @@ mod tests {
             assert_eq!(tcp_sensor_sink.sense(), four);
         });
     }
+
+    #[test]
+    fn test_recorded_ground_truth() {
+        use super::{replay::ground_truth::*, utility::*};
+        use std::time::{Duration, Instant};
+        let start = Instant::now();
+        let truth = RecordedGroundTruth::from_file("test_resources/trajectory.txt", start)
+            .expect("Failed to read trajectory");
+        let midpoint = truth.pose_at(start + Duration::from_secs(1));
+        assert!((midpoint.angle - 0.5).abs() < 1e-9);
+        assert!(midpoint.position.dist((2., 1.).into()) < 1e-9);
+        assert_eq!(truth.pose_at(start), Pose::default());
+        assert_eq!(
+            truth.pose_at(start + Duration::from_secs(5)),
+            Pose {
+                angle: 1.,
+                position: (4., 2.).into()
+            }
+        );
+        assert!(
+            RecordedGroundTruth::from_file("test_resources/trajectory_nan.txt", start).is_err()
+        );
+    }
+
+    #[test]
+    fn test_motion_limits() {
+        use super::{
+            ai::{localization::*, presets::*},
+            sensors::dummy::DummySensor,
+            utility::*,
+        };
+        let map = square_room();
+        let mut mcl: PoseMCL<_, _, _, _, (), ()> = new_test_mcl(
+            map,
+            100,
+            100.,
+            exp_weight(1.05),
+            no_error,
+            uniform_resampler(0.01, 0.01),
+        )
+        .with_motion_limits(
+            MotionLimits {
+                max_angular_rate: Some(1.),
+                max_linear_speed: Some(2.),
+            },
+            0.05,
+        );
+        let before = mcl.belief.clone();
+        let spike = DummySensor::new(Pose {
+            angle: 100.,
+            position: (0., 50.).into(),
+        });
+        // clamped over the update period of 0.05 seconds, then over 0.1 seconds
+        mcl.control_update(&spike);
+        mcl.control_update_with_dt(&spike, 0.1);
+        for (old, new) in before.iter().zip(&mcl.belief) {
+            assert!((new.angle - old.angle - 0.15).abs() < 1e-9);
+            assert!((new.position.y - old.position.y - 0.3).abs() < 1e-9);
+            assert_eq!(new.position.x, old.position.x);
+        }
+    }
+
+    #[cfg(feature = "f32")]
+    #[test]
+    fn test_f32_map_raycast() {
+        use super::{map::*, utility::*};
+        use std::f64::consts::PI;
+        let map = Map2D::new(vec![
+            Object2D::Rectangle((0., 0.).into(), (10., 10.).into()),
+            Object2D::Triangle((2., 2.).into(), (4., 4.).into(), (4., 2.).into()),
+            Object2D::Line((6., 1.).into(), (9., 7.).into()),
+        ]);
+        let compact_map = Map2DF32::from(&map);
+        assert_eq!(
+            Map2DF32::new(vec![
+                Object2D::Rectangle((0., 0.).into(), (10., 10.).into()),
+                Object2D::Triangle((2., 2.).into(), (4., 4.).into(), (4., 2.).into()),
+                Object2D::Line((6., 1.).into(), (9., 7.).into()),
+            ]),
+            compact_map
+        );
+        for i in 0..64 {
+            let start = Pose {
+                angle: i as f64 * PI / 32.,
+                position: (5., 5.).into(),
+            };
+            let expected = map.raycast(start).expect("Failed to intersect");
+            let actual = compact_map.raycast(start).expect("Failed to intersect");
+            assert!(expected.dist(actual) < 1e-4);
+        }
+
+        // a ray through the shared vertex (2, 2) of two walls hits both maps at the vertex
+        let walls = vec![
+            Object2D::Line((2., 2.).into(), (5., 0.).into()),
+            Object2D::Line((0., 5.).into(), (2., 2.).into()),
+        ];
+        let start = Pose {
+            angle: (2f64 - 0.3).atan2(2. - 0.1),
+            position: (0.1, 0.3).into(),
+        };
+        let expected = Map2D::new(walls.clone()).raycast(start).unwrap();
+        let actual = Map2DF32::new(walls).raycast(start).unwrap();
+        assert!(expected.dist((2., 2.).into()) < 1e-9);
+        assert!(expected.dist(actual) < 1e-4);
+    }
+
+    #[test]
+    fn test_belief_histogram() {
+        use super::{
+            ai::{localization::*, presets::*},
+            utility::*,
+        };
+        let map = square_room();
+        let mut mcl: PoseMCL<_, _, _, _, (), ()> = new_test_mcl(
+            map,
+            100,
+            100.,
+            exp_weight(1.05),
+            no_error,
+            uniform_resampler(0.01, 0.01),
+        );
+        let histogram = mcl.belief_histogram(4, 4);
+        assert_eq!(histogram.len(), 16);
+        assert!((histogram.iter().sum::<f64>() - 1.).abs() < 1e-9);
+
+        mcl.belief = vec![
+            Pose {
+                angle: 0.,
+                position: (7.5, 2.5).into(),
+            };
+            50
+        ];
+        let histogram = mcl.belief_histogram(4, 4);
+        assert!((histogram[1 * 4 + 3] - 1.).abs() < 1e-9);
+        assert!((histogram.iter().sum::<f64>() - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fault_injector_dropout() {
+        use super::{
+            ai::{localization::*, presets::*},
+            map::*,
+            sensors::{dummy::DummySensor, *},
+            utility::*,
+        };
+        use std::{f64::INFINITY, sync::Arc};
+        let map = square_room();
+        let mut lidar = DummySensor::new(vec![Point::polar(0., 5.), Point::polar(1., 5.)])
+            .override_limit(Some(0.0..INFINITY))
+            .inject_faults()
+            .with_dropout_rate(1.);
+        let mut object_sensor = DummySensor::new(Some(Point { x: 1., y: 1. }))
+            .inject_faults()
+            .with_dropout_rate(1.);
+        let mut mcl = PoseMCL::new(
+            100,
+            100.,
+            DeathCondition {
+                particle_count_threshold: usize::MAX,
+                particle_concentration_threshold: 0.,
+            },
+            map,
+            exp_weight(1.05),
+            lidar_error(1.1, 1.),
+            // a dropped detection contributes no error
+            |sample: &Pose, z: &FaultInjector<DummySensor<Option<Point>>>, _: &Arc<Map2D>| {
+                z.sense().map_or(0., |point| sample.position.dist(point))
+            },
+            uniform_resampler(0.01, 0.01),
+        );
+        for _ in 0..10 {
+            lidar.update();
+            object_sensor.update();
+            assert!(lidar.sense().is_empty());
+            assert_eq!(object_sensor.sense(), None);
+            mcl.observation_update(&lidar, &object_sensor);
+            // neither dropped sensor gives any information, so the belief keeps its size
+            assert_eq!(mcl.belief.len(), 100);
+            let prediction = mcl.get_prediction();
+            assert!(prediction.angle.is_finite() && prediction.position.x.is_finite());
+        }
+
+        let mut stuck = DummySensor::new(1.).inject_faults().with_stuck_rate(1.);
+        stuck.push(2.);
+        stuck.update();
+        assert_eq!(stuck.sense(), 1.);
+    }
+
+    #[test]
+    fn test_raycast_shared_vertex() {
+        use super::{map::*, utility::*};
+        use std::f64::consts::PI;
+        // a ray through the shared vertex (2, 2) of two walls at an awkward angle
+        let map = Map2D::new(vec![
+            Object2D::Line((2., 2.).into(), (5., 0.).into()),
+            Object2D::Line((0., 5.).into(), (2., 2.).into()),
+        ]);
+        let start = Pose {
+            angle: (2f64 - 0.3).atan2(2. - 0.1),
+            position: (0.1, 0.3).into(),
+        };
+        let first = map.raycast_hit(start).unwrap();
+        assert_eq!(first.line, Some(0));
+        assert!(first.point.dist((2., 2.).into()) < 1e-9);
+        for _ in 0..100 {
+            assert_eq!(map.raycast_hit(start), Some(first));
+        }
+        let reversed = Map2D::new(vec![
+            Object2D::Line((0., 5.).into(), (2., 2.).into()),
+            Object2D::Line((2., 2.).into(), (5., 0.).into()),
+        ]);
+        assert_eq!(reversed.raycast_hit(start).unwrap().line, Some(0));
+        assert!(map
+            .raycast_hit(start.with_angle(PI + start.angle))
+            .is_none());
+    }
+
+    #[test]
+    fn test_kalman_auto_tune() {
+        use super::ai::kalman_filter::{Config, KalmanFilter, LocalizationFilter};
+        use nalgebra::{Matrix6, RowVector6};
+        let config = LocalizationFilter::auto_tune();
+        assert_eq!(config.alpha, 1e-3);
+        assert_eq!(config.beta, 2.);
+        assert_eq!(config.kappa, -3.);
+        assert!(config.is_valid(6));
+        assert!(!Config {
+            kappa: -6.,
+            ..config
+        }
+        .is_valid(6));
+
+        let mut filter = LocalizationFilter::new(
+            Matrix6::identity() * 0.1,
+            RowVector6::from_vec(vec![0., 1., 1., 0., 0., 0.]),
+            config,
+        );
+        filter.prediction_update(0.1, vec![0.1, 0.2, 0.3], Matrix6::identity() * 0.01);
+        filter
+            .measurement_update(
+                RowVector6::from_vec(vec![0.01, 1.1, 0.9, 0.1, 0.2, 0.3]),
+                Matrix6::identity() * 0.01,
+            )
+            .unwrap();
+        assert!(filter.known_state().iter().all(|v| v.is_finite()));
+        assert!(filter.covariance_matrix().iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn test_spike_point() {
+        use super::{sensors::Spike, utility::Point};
+        let spiked = Point { x: 3., y: 4. }.spike(5.);
+        assert!(spiked.dist(Point { x: 6., y: 8. }) < 1e-9);
+        assert_eq!(Point::default().spike(1.), Point::default());
+        // a negative spike longer than the distance stops at the sensor
+        assert_eq!(Point { x: 3., y: 4. }.spike(-10.), Point::default());
+    }
+
+    #[test]
+    fn test_correlated_lidar_error() {
+        use super::{
+            ai::presets::*,
+            sensors::{dummy::DummySensor, *},
+            utility::*,
+        };
+        use nalgebra::DMatrix;
+        use std::f64::{consts::PI, INFINITY};
+        let map = square_room();
+        // the robot is at (5, 5) facing +x, with one beam facing +x and one facing +y
+        let lidar = DummySensor::new(vec![Point::polar(PI, 5.), Point::polar(PI / 2. + PI, 5.)])
+            .override_limit(Some(0.0..INFINITY));
+        // both beams are off by -1.5 (consistent with a correlated bias)
+        let biased = Pose {
+            angle: 0.,
+            position: (3.5, 3.5).into(),
+        };
+        // the beams are off by -1 and +1 (inconsistent with a correlated bias)
+        let opposed = Pose {
+            angle: 0.,
+            position: (4., 6.).into(),
+        };
+
+        let independent = correlated_lidar_error(DMatrix::identity(2, 2), 1., 1.);
+        assert!(independent(&biased, &lidar, &map) > independent(&opposed, &lidar, &map));
+
+        let correlated =
+            correlated_lidar_error(DMatrix::from_row_slice(2, 2, &[1., 0.99, 0.99, 1.]), 1., 1.);
+        assert!(correlated(&biased, &lidar, &map) < correlated(&opposed, &lidar, &map));
+
+        // scans of a different length fall back to independent beams
+        let fallback = correlated_lidar_error(DMatrix::from_row_slice(1, 1, &[1.]), 1., 1.);
+        assert_eq!(
+            fallback(&biased, &lidar, &map),
+            independent(&biased, &lidar, &map)
+        );
+    }
+
+    #[test]
+    fn test_inject_particles() {
+        use super::{
+            ai::{localization::*, presets::*},
+            sensors::{dummy::DummySensor, *},
+            utility::*,
+        };
+        use std::f64::{consts::PI, INFINITY};
+        let map = square_room();
+        let true_pose = Pose {
+            angle: 0.,
+            position: (5., 3.).into(),
+        };
+        let lidar = DummySensor::new(vec![
+            Point::polar(PI, 5.),
+            Point::polar(1.5 * PI, 7.),
+            Point::polar(0., 5.),
+            Point::polar(0.5 * PI, 3.),
+        ])
+        .override_limit(Some(0.0..INFINITY));
+        let mut mcl: PoseMCL<_, _, _, _, _, ()> = new_test_mcl(
+            map,
+            100,
+            INFINITY,
+            exp_weight(10.),
+            lidar_error(1., 1.),
+            uniform_resampler(0.001, 0.001),
+        );
+        // a belief which has diverged to the wrong side of the room
+        mcl.belief = vec![
+            Pose {
+                angle: 0.,
+                position: (2., 8.).into(),
+            };
+            100
+        ];
+        assert!(mcl.last_weights().is_empty());
+        mcl.inject_particles(&[true_pose], 0.1);
+        assert_eq!(mcl.belief.len(), 100);
+        assert_eq!(mcl.belief.iter().filter(|&&p| p == true_pose).count(), 10);
+        // without weights from an observation update the belief is weighted evenly
+        assert_eq!(mcl.last_weights().len(), 100);
+        assert!(mcl.last_weights().iter().all(|w| (w - 0.01).abs() < 1e-9));
+
+        mcl.observation_update(&lidar, &());
+        let near_true = |p: &Pose| p.position.dist(true_pose.position) < 0.1;
+        assert!(mcl.belief.iter().filter(|p| near_true(p)).count() > 90);
+        let weights = mcl.last_weights();
+        assert_eq!(weights.len(), mcl.belief.len());
+        let max_weight = weights.iter().cloned().fold(0., f64::max);
+        for (p, &w) in mcl.belief.iter().zip(weights) {
+            assert_eq!(near_true(p), w == max_weight);
+        }
+    }
+
+    #[test]
+    fn test_uninformative_observation_keeps_belief_size() {
+        use super::ai::{localization::*, presets::*};
+        let map = square_room();
+        let new_mcl = |weight_sum_threshold: f64| -> PoseMCL<_, _, _, _, (), ()> {
+            new_test_mcl(
+                map.clone(),
+                200,
+                weight_sum_threshold,
+                exp_weight(1.05),
+                no_error,
+                uniform_resampler(0.01, 0.01),
+            )
+        };
+        let mut mcl = new_mcl(50.);
+        for _ in 0..5 {
+            mcl.observation_update(&(), &());
+            assert_eq!(mcl.belief.len(), 200);
+        }
+        // seven weights of 1 / 7 add up to just under 1, which must not draw an eighth particle
+        let mut mcl = new_mcl(1.);
+        mcl.belief.truncate(7);
+        for _ in 0..5 {
+            mcl.observation_update(&(), &());
+            assert_eq!(mcl.belief.len(), 7);
+        }
+    }
+
+    #[test]
+    fn test_nearest_segment() {
+        use super::{map::*, utility::*};
+        // lines 0 to 3 are the walls x = 0, y = 6, x = 10 and y = 0, and line 4 is y = 3 from x = 3 to 7
+        let map = Map2D::new(vec![
+            Object2D::Rectangle((0., 0.).into(), (10., 6.).into()),
+            Object2D::Line((3., 3.).into(), (7., 3.).into()),
+        ]);
+        let cases = [
+            ((1., 3.), 0, (0., 3.), 1.),
+            ((5., 5.5), 1, (5., 6.), 0.5),
+            ((9., 2.), 2, (10., 2.), 1.),
+            ((4., 0.5), 3, (4., 0.), 0.5),
+            ((5., 3.5), 4, (5., 3.), 0.5),
+            ((2.5, 3.5), 4, (3., 3.), (0.5f64).sqrt()),
+            // outside of the map, the nearest point can be a corner shared by two lines,
+            // in which case the line with the lowest index is returned
+            ((-3., -4.), 0, (0., 0.), 5.),
+            ((12., 8.), 1, (10., 6.), (8f64).sqrt()),
+        ];
+        for &(point, expected_line, expected_closest, expected_dist) in cases.iter() {
+            let (line, closest, dist) = map.nearest_segment(point.into()).unwrap();
+            assert_eq!(line, expected_line, "nearest line to {:?}", point);
+            assert!(closest.dist(expected_closest.into()) < 1e-9);
+            assert!((dist - expected_dist).abs() < 1e-9);
+        }
+        assert!(Map2D::new(vec![])
+            .nearest_segment(Point::default())
+            .is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "render")]
+    fn test_error_overlay_endpoints() {
+        use super::{replay::render::error_overlay_endpoints, utility::*};
+        let true_pose = Pose {
+            angle: 0.,
+            position: (1., 2.).into(),
+        };
+        let predicted = Pose {
+            angle: 1.,
+            position: (4., 6.).into(),
+        };
+        let (true_point, predicted_point) =
+            error_overlay_endpoints(true_pose, predicted, 10., (5., 5.).into());
+        assert_eq!(true_point, Point { x: 15., y: 25. });
+        assert_eq!(predicted_point, Point { x: 45., y: 65. });
+        assert_eq!(
+            true_point.dist(predicted_point),
+            10. * true_pose.position.dist(predicted.position)
+        );
+    }
+
+    #[test]
+    fn test_simulation_circular_trajectory() {
+        use super::{
+            ai::{localization::*, presets::*},
+            harness::Simulation,
+            map::*,
+            sensors::dummy::{DummyLidar, DummyPositionSensor},
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::{f64::consts::FRAC_PI_2, sync::Arc, time::Duration};
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle((0., 0.).into(), (20., 10.).into()),
+            Object2D::Line((14., 0.).into(), (14., 3.).into()),
+        ]));
+        let trajectory = |t: f64| Pose {
+            angle: t + FRAC_PI_2,
+            position: Point { x: 8., y: 5. } + Point::polar(t, 3.),
+        };
+        let start = trajectory(0.);
+        let lidar = DummyLidar::new(
+            map.clone(),
+            start,
+            Normal::new(0., 0.0001).unwrap(),
+            Normal::new(0., 0.001).unwrap(),
+            36,
+            Duration::from_secs(0),
+            Pose::default(),
+            None,
+        );
+        let motion_sensor = DummyPositionSensor::new(
+            start,
+            Pose {
+                angle: 0.01,
+                position: (0.05, 0.05).into(),
+            },
+        );
+        let mcl: PoseMCL<_, _, _, _, _, ()> = PoseMCL::from_distributions(
+            (
+                Normal::new(start.angle, 0.1).unwrap(),
+                (
+                    Normal::new(start.position.x, 0.5).unwrap(),
+                    Normal::new(start.position.y, 0.5).unwrap(),
+                ),
+            ),
+            1000,
+            1000.,
+            DeathCondition {
+                particle_count_threshold: usize::MAX,
+                particle_concentration_threshold: 0.,
+            },
+            map,
+            exp_weight(10.),
+            lidar_error(1., 1.),
+            |_: &Pose, _: &(), _: &Arc<Map2D>| 0.,
+            uniform_resampler(0.02, 0.05),
+        );
+        let mut sim = Simulation::new(trajectory, 0.05, lidar, motion_sensor, mcl);
+        let results = sim.run(100);
+        assert_eq!(results.len(), 100);
+        assert!((sim.time() - 5.).abs() < 1e-9);
+        let mean_error = results
+            .iter()
+            .map(|(true_pose, prediction)| true_pose.position.dist(prediction.position))
+            .sum::<f64>()
+            / 100.;
+        assert!(mean_error < 0.25, "mean position error {}", mean_error);
+    }
+
+    #[test]
+    fn test_raycast_swept() {
+        use super::{map::*, utility::*};
+        let map = Map2D::new(vec![Object2D::Rectangle(
+            (0., 0.).into(),
+            (10., 10.).into(),
+        )]);
+        let from = Pose {
+            angle: 0.,
+            position: (2., 2.).into(),
+        };
+        let to = Pose {
+            angle: 1.,
+            position: (6., 4.).into(),
+        };
+        let halfway = Pose {
+            angle: 0.5,
+            position: (4., 3.).into(),
+        };
+        assert_eq!(map.raycast_swept(from, to, 0.5), map.raycast(halfway));
+        assert_eq!(map.raycast_swept(from, to, 0.), map.raycast(from));
+        assert_eq!(map.raycast_swept(from, to, 1.), map.raycast(to));
+        assert_ne!(map.raycast_swept(from, to, 0.5), map.raycast(from));
+    }
+
+    #[test]
+    fn test_kinematic_state_clamp() {
+        use super::utility::*;
+        let bounds = Point::default()..Point { x: 10., y: 10. };
+        let state = KinematicState {
+            angle: 1.,
+            position: (12., 5.).into(),
+            vel_angle: 0.5,
+            velocity: (2., 3.).into(),
+        };
+
+        let clamped = state.clamp_position(bounds.clone());
+        assert_eq!(clamped.position, Point { x: 10., y: 5. });
+        assert_eq!(clamped.velocity, state.velocity);
+        assert_eq!(clamped.vel_angle, state.vel_angle);
+
+        let mut stopped = state;
+        let diff_vel = stopped.clamp_control_update(bounds.clone());
+        assert_eq!(stopped.position, Point { x: 10., y: 5. });
+        assert_eq!(stopped.velocity, Point { x: 0., y: 3. });
+        assert_eq!(stopped.vel_angle, 0.);
+        assert_eq!(
+            diff_vel,
+            Pose {
+                angle: -0.5,
+                position: (-2., 0.).into(),
+            }
+        );
+
+        // inside the bounds neither changes anything
+        let inside = state.with_position((5., 5.).into());
+        assert_eq!(inside.clamp_position(bounds.clone()), inside);
+        let mut inside_stopped = inside;
+        assert_eq!(inside_stopped.clamp_control_update(bounds), Pose::default());
+        assert_eq!(inside_stopped, inside);
+    }
+
+    #[test]
+    fn test_get_prediction_checked() {
+        use super::{
+            ai::{localization::*, presets::*},
+            utility::*,
+        };
+        let map = square_room();
+        let mut mcl: PoseMCL<_, _, _, _, (), ()> = new_test_mcl(
+            map,
+            500,
+            500.,
+            exp_weight(1.05),
+            no_error,
+            uniform_resampler(0.01, 0.01),
+        );
+        assert_eq!(mcl.get_prediction_checked(1.), None);
+
+        let converged = Pose {
+            angle: 1.,
+            position: (3., 4.).into(),
+        };
+        mcl.belief = (0..100)
+            .map(|i| {
+                converged
+                    + Pose {
+                        angle: 0.,
+                        position: Point::polar(i as f64, 0.2),
+                    }
+            })
+            .collect();
+        let prediction = mcl.get_prediction_checked(1.).unwrap();
+        assert!(prediction.position.dist(converged.position) < 0.2);
+        assert_eq!(mcl.get_prediction_checked(0.1), None);
+    }
+
+    #[test]
+    fn test_path_clear() {
+        use super::{map::*, utility::*};
+        // a wall ending 0.5 below the path
+        let map = Map2D::new(vec![Object2D::Line((5., 0.).into(), (5., 1.5).into())]);
+        let (from, to) = (Point { x: 0., y: 2. }, Point { x: 10., y: 2. });
+        assert!(map.path_clear(from, to, 0.));
+        assert!(map.path_clear(from, to, 0.4));
+        assert!(!map.path_clear(from, to, 0.6));
+        // a path crossing the wall
+        assert!(!map.path_clear((0., 1.).into(), (10., 1.).into(), 0.));
+        // a path ending before the wall
+        assert!(map.path_clear((0., 1.).into(), (4., 1.).into(), 0.5));
+        assert!(!map.path_clear((0., 1.).into(), (4., 1.).into(), 1.5));
+    }
+
+    #[test]
+    fn test_detect_ambiguity() {
+        use super::{
+            ai::{localization::*, presets::*},
+            map::*,
+            sensors::{dummy::DummyLidar, *},
+            utility::*,
+        };
+        use rand_distr::{Distribution, Normal};
+        use std::{f64::consts::PI, sync::Arc, time::Duration};
+        // two identical rooms side by side, each with a short wall to break its own symmetry
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle((0., 0.).into(), (10., 6.).into()),
+            Object2D::Rectangle((10., 0.).into(), (20., 6.).into()),
+            Object2D::Line((7., 0.).into(), (7., 2.).into()),
+            Object2D::Line((17., 0.).into(), (17., 2.).into()),
+        ]));
+        let true_pose = Pose {
+            angle: 0.,
+            position: (3., 3.).into(),
+        };
+        let mut lidar = DummyLidar::new(
+            map.clone(),
+            true_pose,
+            Normal::new(0., 0.0001).unwrap(),
+            Normal::new(0., 0.001).unwrap(),
+            36,
+            Duration::from_secs(0),
+            Pose::default(),
+            None,
+        );
+        lidar.update_with_maps(vec![]);
+        let mut mcl: PoseMCL<_, _, _, _, _, ()> = new_test_mcl(
+            map,
+            2000,
+            2000.,
+            exp_weight(10.),
+            lidar_error(1., 1.),
+            uniform_resampler(0.02, 0.05),
+        );
+        // a belief split between the matching pose in each room
+        let mut rng = rand::thread_rng();
+        let noise = Normal::new(0., 0.3).unwrap();
+        mcl.belief = (0..2000)
+            .map(|i| Pose {
+                angle: noise.sample(&mut rng) / 10.,
+                position: Point {
+                    x: if i % 2 == 0 { 3. } else { 13. } + noise.sample(&mut rng),
+                    y: 3. + noise.sample(&mut rng),
+                },
+            })
+            .collect();
+        for _ in 0..3 {
+            mcl.observation_update(&lidar, &());
+        }
+        let mut hypotheses = mcl.detect_ambiguity(1., 1., 0.2).unwrap();
+        assert_eq!(hypotheses.len(), 2);
+        hypotheses.sort_by(|a, b| a.position.x.partial_cmp(&b.position.x).unwrap());
+        assert!(hypotheses[0].position.dist(true_pose.position) < 0.5);
+        assert!(hypotheses[1].position.dist((13., 3.).into()) < 0.5);
+
+        mcl.belief.retain(|p| p.position.x < 10.);
+        assert_eq!(mcl.detect_ambiguity(1., 1., 0.2), None);
+
+        // facing opposite ways in the same place is only ambiguous when angles are weighted,
+        // and angles on either side of 0 are in the same cluster
+        let true_pose = true_pose.with_angle(0.01);
+        mcl.belief = (0..100)
+            .map(|i| match i % 5 {
+                0 => true_pose,
+                1 => true_pose.with_angle(-0.01),
+                _ => true_pose.with_angle(PI),
+            })
+            .collect();
+        let hypotheses = mcl.detect_ambiguity(1., 1., 0.2).unwrap();
+        assert_eq!(hypotheses.len(), 2);
+        assert!(hypotheses[0].angle_dist(true_pose.with_angle(PI)) < 1e-9);
+        assert!(hypotheses[1].angle_dist(true_pose.with_angle(0.)) < 1e-9);
+        assert_eq!(mcl.detect_ambiguity(1., 0., 0.2), None);
+    }
+
+    #[test]
+    fn test_poll_scheduler() {
+        use super::sensors::{dummy::DummySensor, *};
+        use std::time::{Duration, Instant};
+        struct CountingSensor {
+            updates: Vec<Instant>,
+            now: Instant,
+        }
+        impl Sensor for CountingSensor {
+            type Output = usize;
+
+            fn update(&mut self) {
+                self.updates.push(self.now);
+            }
+
+            fn sense(&self) -> Self::Output {
+                self.updates.len()
+            }
+
+            fn min_interval(&self) -> Option<Duration> {
+                Some(Duration::from_millis(100))
+            }
+        }
+
+        let start = Instant::now();
+        let mut sensor = CountingSensor {
+            updates: vec![],
+            now: start,
+        }
+        .map(|count| count * 2);
+        assert_eq!(sensor.min_interval(), Some(Duration::from_millis(100)));
+        let mut scheduler = PollScheduler::new();
+        for i in 0..100 {
+            let now = start + Duration::from_millis(10 * i);
+            sensor.internal_sensor.now = now;
+            scheduler.poll(&mut sensor, now);
+        }
+        let updates = &sensor.internal_sensor.updates;
+        assert_eq!(updates.len(), 10);
+        for pair in updates.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(100));
+        }
+
+        // sensors without a minimum interval are always polled
+        let mut dummy = DummySensor::new(0);
+        let mut scheduler = PollScheduler::new();
+        assert_eq!(dummy.min_interval(), None);
+        assert!(scheduler.poll(&mut dummy, start));
+        assert!(scheduler.poll(&mut dummy, start));
+    }
+
+    #[test]
+    fn test_stable_resampling() {
+        use super::ai::{localization::*, presets::*};
+        let map = square_room();
+        let new_mcl = |stable| -> PoseMCL<_, _, _, _, (), ()> {
+            new_test_mcl(
+                map.clone(),
+                200,
+                200.,
+                exp_weight(1.05),
+                no_error,
+                uniform_resampler(0.001, 0.001),
+            )
+            .with_stable_resampling(stable)
+        };
+        let kept_index = |mcl: &mut PoseMCL<_, _, _, _, (), ()>| {
+            let before = mcl.belief.clone();
+            mcl.observation_update(&(), &());
+            before
+                .iter()
+                .zip(&mcl.belief)
+                .filter(|(b, a)| b.position.dist(a.position) < 0.01)
+                .count()
+        };
+        // with uniform weights, about 1 - 1/e of the particles survive resampling
+        let mut stable = new_mcl(true);
+        assert!(kept_index(&mut stable) > 100);
+        assert_eq!(stable.belief.len(), 200);
+        let mut unstable = new_mcl(false);
+        assert!(kept_index(&mut unstable) < 20);
+    }
+
+    #[test]
+    fn test_raycast_cloud() {
+        use super::{map::*, utility::*};
+        use std::f64::consts::PI;
+        let map = Map2D::new(vec![Object2D::Rectangle(
+            (0., 0.).into(),
+            (10., 10.).into(),
+        )]);
+        let origin = Pose {
+            angle: 0.3,
+            position: (3., 4.).into(),
+        };
+        let bearings: Vec<f64> = (0..36).map(|i| i as f64 * PI / 18.).collect();
+        let cloud = map.raycast_cloud(origin, &bearings, None);
+        assert_eq!(cloud.len(), 36);
+        for point in &cloud {
+            let wall_dist = point
+                .x
+                .abs()
+                .min(point.y.abs())
+                .min((10. - point.x).abs())
+                .min((10. - point.y).abs());
+            assert!(wall_dist < 1e-9);
+        }
+        assert!(cloud[0].dist(map.raycast(origin).unwrap()) < 1e-9);
+        // hits further than the max range are skipped
+        let limited = map.raycast_cloud(origin, &bearings, Some(5.));
+        assert!(!limited.is_empty() && limited.len() < cloud.len());
+        assert!(limited.iter().all(|p| p.dist(origin.position) <= 5.));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "weight_from_error must not increase with error")]
+    fn test_increasing_weight_from_error() {
+        use super::ai::{localization::*, presets::*};
+        let map = square_room();
+        let _: PoseMCL<_, _, _, _, (), ()> = new_test_mcl(
+            map,
+            10,
+            10.,
+            |error: &f64| 1. + error,
+            no_error,
+            uniform_resampler(0.01, 0.01),
+        );
+    }
+
+    #[test]
+    fn test_nan_control_update() {
+        use super::{
+            ai::{localization::*, presets::*},
+            sensors::dummy::DummySensor,
+            utility::*,
+        };
+        use std::f64::NAN;
+        let map = square_room();
+        let mut mcl: PoseMCL<_, _, _, _, (), ()> = new_test_mcl(
+            map,
+            100,
+            100.,
+            exp_weight(1.05),
+            no_error,
+            uniform_resampler(0.01, 0.01),
+        );
+        let before = mcl.belief.clone();
+        mcl.control_update(&DummySensor::new(Pose {
+            angle: 0.,
+            position: (NAN, 1.).into(),
+        }));
+        assert_eq!(mcl.belief, before);
+        mcl.control_update_with_dt(
+            &DummySensor::new(Pose {
+                angle: NAN,
+                position: (0., 0.).into(),
+            }),
+            0.1,
+        );
+        assert_eq!(mcl.belief, before);
+
+        // particles which are already not finite are dropped
+        mcl.belief[0].position.x = NAN;
+        mcl.control_update(&DummySensor::new(Pose::default()));
+        assert_eq!(mcl.belief.len(), 99);
+        assert!(mcl.belief.iter().all(|p| p.is_finite()));
+        assert!(mcl.get_prediction().is_finite());
+    }
+
+    #[test]
+    fn test_map_sdf_raymarch() {
+        use super::{map::*, utility::*};
+        use std::f64::consts::PI;
+        let center = Point { x: 5., y: 5. };
+        let radius = 2.;
+        let map = MapSdf::new((10., 10.).into(), move |p: Point| p.dist(center) - radius);
+        assert!((map.distance((0., 5.).into()) - 3.).abs() < 1e-9);
+        assert!(map.distance(center) < 0.);
+
+        let start = Pose {
+            angle: 0.,
+            position: (1., 5.).into(),
+        };
+        let dist = map.raymarch(start, 100, 1e-9).unwrap();
+        assert!((dist - 2.).abs() < 1e-6);
+        // a ray grazing the circle at an angle
+        let offset = Pose {
+            angle: 0.,
+            position: (1., 6.).into(),
+        };
+        let analytic = 4. - (radius.powi(2) - 1f64.powi(2)).sqrt();
+        assert!((map.raycast_distance(offset).unwrap() - analytic).abs() < 1e-5);
+        // rays that miss leave the map
+        assert_eq!(map.raymarch(start.with_angle(PI), 100, 1e-9), None);
+
+        // the same trait gives distances for segment maps
+        let walls = Map2D::new(vec![Object2D::Rectangle(
+            (0., 0.).into(),
+            (10., 10.).into(),
+        )]);
+        assert!((walls.raycast_distance(start).unwrap() - 9.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_belief_summary() {
+        use super::{
+            ai::localization::BeliefSummary,
+            utility::{Point, Pose},
+        };
+        use std::f64::consts::PI;
+
+        let belief: Vec<Pose> = (0..50)
+            .map(|i| {
+                let t = i as f64;
+                Pose {
+                    // angles around 0 that wrap to just under 2π
+                    angle: ((t * 0.37).sin() * 0.2 + 2. * PI) % (2. * PI),
+                    position: Point {
+                        x: 3. + (t * 1.3).cos(),
+                        y: 7. + (t * 0.7).sin() * 0.5 + 0.1 * (t * 1.3).cos(),
+                    },
+                }
+            })
+            .collect();
+        let summary = BeliefSummary::from(&belief[..]);
+
+        // separately computed values
+        let count = belief.len() as f64;
+        let signed_angle = |angle: f64| if angle > PI { angle - 2. * PI } else { angle };
+        let values: Vec<[f64; 3]> = belief
+            .iter()
+            .map(|pose| [signed_angle(pose.angle), pose.position.x, pose.position.y])
+            .collect();
+        let mut mean = [0.; 3];
+        for value in &values {
+            for k in 0..3 {
+                mean[k] += value[k] / count;
+            }
+        }
+        assert!((signed_angle(summary.mean.angle) - mean[0]).abs() < 1e-9);
+        assert!((summary.mean.position.x - mean[1]).abs() < 1e-9);
+        assert!((summary.mean.position.y - mean[2]).abs() < 1e-9);
+        for row in 0..3 {
+            for col in 0..3 {
+                let covariance = values
+                    .iter()
+                    .map(|value| (value[row] - mean[row]) * (value[col] - mean[col]))
+                    .sum::<f64>()
+                    / count;
+                assert!((summary.covariance[row][col] - covariance).abs() < 1e-9);
+            }
+        }
+        let c = summary.covariance;
+        let determinant = c[0][0] * (c[1][1] * c[2][2] - c[1][2] * c[2][1])
+            - c[0][1] * (c[1][0] * c[2][2] - c[1][2] * c[2][0])
+            + c[0][2] * (c[1][0] * c[2][1] - c[1][1] * c[2][0]);
+        let entropy = 1.5 * (1. + (2. * PI).ln()) + 0.5 * determinant.ln();
+        assert!((summary.entropy - entropy).abs() < 1e-9);
+        assert!((summary.effective_sample_size - count).abs() < 1e-9);
+
+        let mut weights = vec![0.; belief.len()];
+        weights[0] = 1.;
+        weights[1] = 1.;
+        let weighted = BeliefSummary::new(&belief, &weights);
+        assert!((weighted.effective_sample_size - 2.).abs() < 1e-9);
+        assert_eq!(weighted.covariance, summary.covariance);
+    }
+
+    #[test]
+    fn test_deterministic_resampling() {
+        use super::{ai::presets::*, map::*, utility::*};
+        use std::sync::Arc;
+
+        assert_eq!(
+            uniform_resampler(0., 0.)(10),
+            Pose::default(),
+            "zero margins should not sample noise"
+        );
+
+        let map = square_room();
+        let parents: Vec<Pose> = (0..200)
+            .map(|_| Pose::random(0.0..6., 0.0..10., 0.0..10.))
+            .collect();
+        let run = |seed: u64| {
+            let mut mcl = new_test_mcl(
+                map.clone(),
+                200,
+                1.,
+                exp_weight(1.1),
+                |pose: &Pose, _: &(), _: &Arc<Map2D>| pose.position.x,
+                no_resampler(),
+            )
+            .with_seed(seed);
+            mcl.belief = parents.clone();
+            mcl.observation_update(&(), &());
+            assert!(
+                mcl.belief.iter().all(|particle| parents.contains(particle)),
+                "every resampled particle should be an exact copy of a parent"
+            );
+            mcl.belief
+        };
+        assert_eq!(
+            run(4904),
+            run(4904),
+            "the same seed should draw the same parents"
+        );
+    }
+
+    #[test]
+    fn test_raycast_beam() {
+        use super::{
+            map::{Map2D, Object2D},
+            utility::{Point, Pose},
+        };
+
+        let map = Map2D::new(vec![
+            Object2D::Rectangle(Point { x: 0., y: 0. }, Point { x: 10., y: 10. }),
+            // a thin obstacle just beside the center of the beam
+            Object2D::Line(Point { x: 5., y: 5.1 }, Point { x: 5., y: 6. }),
+        ]);
+        let start = Pose {
+            angle: 0.,
+            position: Point { x: 1., y: 5. },
+        };
+
+        let pencil = map.raycast(start).unwrap();
+        assert!((pencil.dist(start.position) - 9.).abs() < 1e-9);
+        assert_eq!(map.raycast_beam(start, 0.1, 1), Some(pencil));
+
+        let beam = map.raycast_beam(start, 0.1, 5).unwrap();
+        let dist = beam.dist(start.position);
+        assert!(
+            dist > 4. && dist < 4.01,
+            "beam should catch the obstacle, got {}",
+            dist
+        );
+        assert!(
+            (beam.y - 5.).abs() < 1e-9,
+            "the hit should be along the center of the beam"
+        );
+    }
+
+    #[test]
+    fn test_clone_localizer() {
+        use super::{ai::presets::*, map::*, sensors::dummy::DummySensor, utility::*};
+        use std::sync::Arc;
+
+        let map = square_room();
+        let original = new_test_mcl(
+            map,
+            100,
+            1.,
+            exp_weight(1.1),
+            |pose: &Pose, _: &(), _: &Arc<Map2D>| pose.position.x,
+            uniform_resampler(0.01, 0.01),
+        )
+        .with_seed(4904);
+        let starting_belief = original.belief.clone();
+
+        let mut copy = original.clone();
+        assert_eq!(copy.belief, starting_belief);
+        copy.observation_update(&(), &());
+        copy.control_update(&DummySensor::new(Pose {
+            angle: 0.,
+            position: Point { x: 1., y: 1. },
+        }));
+        assert_ne!(copy.belief, starting_belief);
+        assert_eq!(original.belief, starting_belief);
+        assert!(original.last_weights().is_empty());
+        assert!(!copy.last_weights().is_empty());
+    }
+
+    #[test]
+    fn test_raycast_reflective() {
+        use super::{
+            map::{Map2D, Object2D},
+            utility::{Point, Pose},
+        };
+        use std::f64::consts::FRAC_PI_4;
+
+        let map = Map2D::new(vec![
+            Object2D::Line(Point { x: 0., y: 0. }, Point { x: 0., y: 10. }),
+            Object2D::Line(Point { x: 0., y: 0. }, Point { x: 10., y: 0. }),
+            Object2D::Line(Point { x: 0., y: 10. }, Point { x: 10., y: 10. }),
+            Object2D::Mirror(Point { x: 10., y: 0. }, Point { x: 10., y: 10. }),
+        ]);
+        let start = Pose {
+            angle: FRAC_PI_4,
+            position: Point { x: 6., y: 1. },
+        };
+
+        // the ray hits the mirror at (10, 5) and is reflected to (5, 10),
+        // which is where the unfolded ray hits the mirror image of the top wall at (15, 10)
+        let (point, path_length) = map.raycast_reflective(start, 1).unwrap();
+        assert!(point.dist(Point { x: 5., y: 10. }) < 1e-9);
+        let unfolded = start.position.dist(Point { x: 15., y: 10. });
+        assert!((path_length - unfolded).abs() < 1e-9);
+
+        // without bounces, the mirror is a regular line
+        let (point, path_length) = map.raycast_reflective(start, 0).unwrap();
+        assert!(point.dist(Point { x: 10., y: 5. }) < 1e-9);
+        assert!((path_length - map.raycast_hit(start).unwrap().dist).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_masked_lidar_error() {
+        use super::{
+            ai::{localization::*, presets::*},
+            map::*,
+            sensors::{dummy::DummyLidar, *},
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::{sync::Arc, time::Duration};
+
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle(Point { x: 0., y: 0. }, Point { x: 10., y: 10. }),
+            Object2D::Triangle(
+                Point { x: 2., y: 2. },
+                Point { x: 3., y: 2. },
+                Point { x: 2., y: 4. },
+            ),
+        ]));
+        let true_pose = Pose {
+            angle: 0.5,
+            position: Point { x: 6., y: 4. },
+        };
+        // another robot standing next to the lidar and occluding several beams
+        let intruder = Arc::new(Map2D::new(vec![Object2D::Rectangle(
+            Point { x: 7., y: 3. },
+            Point { x: 8., y: 5. },
+        )]));
+        let mut lidar = DummyLidar::new(
+            map.clone(),
+            true_pose,
+            Normal::new(0., 0.0001).unwrap(),
+            Normal::new(0., 0.001).unwrap(),
+            36,
+            Duration::from_secs(0),
+            Pose::default(),
+            None,
+        )
+        .masked();
+        lidar.internal_sensor.update_with_maps(vec![intruder]);
+
+        let unmasked_error = lidar_error(1., 1.);
+        let masked_error = masked_lidar_error(1., 1.);
+        assert!(unmasked_error(&true_pose, &lidar.internal_sensor, &map) > 0.1);
+        lidar.set_mask(vec![Point { x: 6.9, y: 2.9 }..Point { x: 8.1, y: 5.1 }]);
+        assert!(masked_error(&true_pose, &lidar, &map) < 0.01);
+
+        let mut mcl: PoseMCL<_, _, _, _, _, ()> = PoseMCL::from_distributions(
+            (
+                Normal::new(true_pose.angle, 0.1).unwrap(),
+                (
+                    Normal::new(true_pose.position.x, 0.5).unwrap(),
+                    Normal::new(true_pose.position.y, 0.5).unwrap(),
+                ),
+            ),
+            1000,
+            1000.,
+            DeathCondition {
+                particle_count_threshold: usize::MAX,
+                particle_concentration_threshold: 0.,
+            },
+            map,
+            exp_weight(10.),
+            masked_error,
+            |_: &Pose, _: &(), _: &Arc<Map2D>| 0.,
+            uniform_resampler(0.02, 0.05),
+        );
+        for _ in 0..10 {
+            mcl.observation_update(&lidar, &());
+        }
+        let prediction = mcl.get_prediction();
+        assert!(
+            prediction.position.dist(true_pose.position) < 0.3,
+            "masked belief should converge to the true pose, got {:?}",
+            prediction
+        );
+    }
+
+    #[test]
+    fn test_steps_to_converge() {
+        use super::{
+            ai::{localization::*, presets::*},
+            harness::{is_converged, Simulation},
+            map::*,
+            sensors::dummy::{DummyLidar, DummyPositionSensor},
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::{f64::consts::PI, sync::Arc, time::Duration};
+
+        assert!(is_converged(
+            Pose::default(),
+            Pose {
+                angle: 2. * PI - 0.05,
+                position: Point { x: 0.1, y: 0. },
+            },
+            0.2,
+            0.1
+        ));
+        // the thresholds are independent, so being close to both of them still counts
+        let near = Pose {
+            angle: 0.09,
+            position: Point { x: 0.19, y: 0. },
+        };
+        assert!(is_converged(Pose::default(), near, 0.2, 0.1));
+        assert!(is_converged(Pose::default(), near.with_angle(0.), 0.2, 0.));
+        assert!(!is_converged(Pose::default(), near, 0.2, 0.));
+
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle(Point { x: 0., y: 0. }, Point { x: 10., y: 10. }),
+            Object2D::Triangle(
+                Point { x: 2., y: 2. },
+                Point { x: 4., y: 2. },
+                Point { x: 2., y: 5. },
+            ),
+        ]));
+        let true_pose = Pose {
+            angle: 1.,
+            position: Point { x: 7., y: 6. },
+        };
+        let steps_to_converge = |particles: usize, resampling_margin: f64| {
+            let lidar = DummyLidar::new(
+                map.clone(),
+                true_pose,
+                Normal::new(0., 0.0001).unwrap(),
+                Normal::new(0., 0.001).unwrap(),
+                36,
+                Duration::from_secs(0),
+                Pose::default(),
+                None,
+            );
+            let motion_sensor = DummyPositionSensor::new(true_pose, Pose::default());
+            let mcl: PoseMCL<_, _, _, _, _, ()> = new_test_mcl(
+                map.clone(),
+                particles,
+                particles as f64,
+                exp_weight(3.),
+                lidar_error(1., 1.),
+                uniform_resampler(resampling_margin / 2., resampling_margin),
+            );
+            let mut sim = Simulation::new(|_| true_pose, 0.1, lidar, motion_sensor, mcl);
+            sim.steps_to_converge(0.3, 0.2, 50)
+        };
+
+        let well_tuned = steps_to_converge(3000, 0.1).expect("well tuned config should converge");
+        let poorly_tuned = steps_to_converge(3000, 2.);
+        assert!(
+            poorly_tuned.map_or(true, |steps| steps > well_tuned),
+            "well tuned config took {} steps but poorly tuned config took {:?}",
+            well_tuned,
+            poorly_tuned
+        );
+    }
+
+    #[test]
+    fn test_scale() {
+        use super::utility::{KinematicState, Point, Pose};
+        use std::f64::consts::PI;
+
+        let rate = Pose {
+            angle: 3.,
+            position: Point { x: 2., y: -4. },
+        };
+        let dt = 0.25;
+        let delta = rate.scale(dt);
+        assert_eq!(delta.angle, 0.75);
+        assert_eq!(delta.position, Point { x: 0.5, y: -1. });
+        // scaling does not normalize angle, but multiplying does
+        assert_eq!(rate.scale(4.).angle, 12.);
+        assert!(((rate * 4.).angle - (12. % (2. * PI))).abs() < 1e-12);
+        assert_eq!(rate.scale(dt), rate / (1. / dt));
+
+        let state = KinematicState {
+            angle: 3.,
+            position: Point { x: 2., y: -4. },
+            vel_angle: 1.,
+            velocity: Point { x: -8., y: 6. },
+        };
+        assert_eq!(
+            state.scale(dt),
+            KinematicState {
+                angle: 0.75,
+                position: Point { x: 0.5, y: -1. },
+                vel_angle: 0.25,
+                velocity: Point { x: -2., y: 1.5 },
+            }
+        );
+        assert_eq!(state.scale(4.).angle, 12.);
+        assert_eq!(state * dt, state.scale(dt));
+    }
+
+    #[test]
+    fn test_raycast_concave_maps() {
+        use super::{
+            map::{Map2D, Object2D},
+            utility::{Point, Pose},
+        };
+        use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+        // checks that each ray hits `expected` whichever order the lines are in
+        let check = |corners: &[(f64, f64)], rays: &[(Pose, Point)]| {
+            let lines: Vec<Object2D> = corners
+                .iter()
+                .zip(corners.iter().cycle().skip(1))
+                .map(|(&a, &b)| Object2D::Line(a.into(), b.into()))
+                .collect();
+            let maps = [
+                Map2D::new(lines.clone()),
+                Map2D::new(lines.into_iter().rev()),
+            ];
+            for map in &maps {
+                for &(start, expected) in rays {
+                    let hit = map.raycast(start).unwrap();
+                    assert!(
+                        hit.dist(expected) < 1e-9,
+                        "ray from {:?} hit {:?} instead of {:?}",
+                        start,
+                        hit,
+                        expected
+                    );
+                }
+            }
+        };
+        let pose = |x: f64, y: f64, angle: f64| Pose {
+            angle,
+            position: Point { x, y },
+        };
+
+        let l_shape = [
+            (0., 0.),
+            (10., 0.),
+            (10., 4.),
+            (4., 4.),
+            (4., 10.),
+            (0., 10.),
+        ];
+        check(
+            &l_shape,
+            &[
+                (pose(2., 2., 0.), Point { x: 10., y: 2. }),
+                // into the inner corner, with more walls behind the nearest one
+                (pose(2., 8., -FRAC_PI_4), Point { x: 4., y: 6. }),
+                (pose(8., 2., 3. * FRAC_PI_4), Point { x: 6., y: 4. }),
+            ],
+        );
+
+        let u_shape = [
+            (0., 0.),
+            (10., 0.),
+            (10., 10.),
+            (7., 10.),
+            (7., 3.),
+            (3., 3.),
+            (3., 10.),
+            (0., 10.),
+        ];
+        check(
+            &u_shape,
+            &[
+                (pose(1., 5., 0.), Point { x: 3., y: 5. }),
+                (pose(5., 1., FRAC_PI_2), Point { x: 5., y: 3. }),
+                (pose(8., 8., PI), Point { x: 7., y: 8. }),
+                (pose(1., 1., FRAC_PI_4), Point { x: 3., y: 3. }),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_landmark_error_fov() {
+        use super::{
+            ai::{localization::*, presets::*},
+            map::*,
+            sensors::dummy::DummyObjectSensor,
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::{f64::consts::FRAC_PI_2, sync::Arc};
+
+        let landmark = |x: f64, y: f64| {
+            Object2D::Target(
+                Pose {
+                    angle: 0.,
+                    position: Point { x, y },
+                }
+                .into(),
+            )
+        };
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle(Point { x: 0., y: 0. }, Point { x: 10., y: 10. }),
+            landmark(9., 5.),
+            landmark(8., 7.5),
+            landmark(3., 3.5),
+            // just outside of the 90° fov of a robot at (5, 5) facing +x
+            landmark(7., 7.2),
+        ]));
+        let true_pose = Pose {
+            angle: 0.,
+            position: Point { x: 5., y: 5. },
+        };
+        let visible = map.cull_points(
+            true_pose,
+            Point {
+                x: FRAC_PI_2,
+                y: 10.,
+            },
+            None,
+        );
+        assert_eq!(visible.len(), 2);
+        assert!(visible
+            .iter()
+            .all(|landmark| landmark.position.without_z() != Point { x: 2., y: 2.2 }));
+
+        let detector = DummyObjectSensor::new(
+            FRAC_PI_2,
+            map.clone(),
+            Pose::default(),
+            true_pose,
+            None,
+            Point::default(),
+        );
+        let error = landmark_error(FRAC_PI_2, None, 1., 1.);
+        assert!(error(&true_pose, &detector, &map) < 1e-9);
+
+        let mut mcl: PoseMCL<_, _, _, _, (), _> = PoseMCL::from_distributions(
+            (
+                Normal::new(true_pose.angle, 0.05).unwrap(),
+                (
+                    Normal::new(true_pose.position.x, 0.5).unwrap(),
+                    Normal::new(true_pose.position.y, 0.5).unwrap(),
+                ),
+            ),
+            1000,
+            1000.,
+            DeathCondition {
+                particle_count_threshold: usize::MAX,
+                particle_concentration_threshold: 0.,
+            },
+            map,
+            exp_weight(10.),
+            |_: &Pose, _: &(), _: &Arc<Map2D>| 0.,
+            error,
+            uniform_resampler(0.01, 0.05),
+        );
+        for _ in 0..10 {
+            mcl.observation_update(&(), &detector);
+        }
+        let prediction = mcl.get_prediction();
+        assert!(
+            prediction.position.dist(true_pose.position) < 0.3,
+            "belief should converge using the landmarks in the fov, got {:?}",
+            prediction
+        );
+    }
+
+    #[test]
+    fn test_health_monitor_recovery() {
+        use super::{
+            ai::{health::*, localization::*, presets::*},
+            map::*,
+            sensors::{dummy::DummySensor, *},
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::sync::Arc;
+
+        let map = square_room();
+        let mut mcl: PoseMCL<_, _, _, _, (), ()> = PoseMCL::from_distributions(
+            (
+                Normal::new(1., 0.01).unwrap(),
+                (
+                    Normal::new(5., 0.05).unwrap(),
+                    Normal::new(5., 0.05).unwrap(),
+                ),
+            ),
+            200,
+            1.,
+            DeathCondition {
+                particle_count_threshold: usize::MAX,
+                particle_concentration_threshold: 0.,
+            },
+            map,
+            exp_weight(10.),
+            |_: &Pose, _: &(), _: &Arc<Map2D>| 0.,
+            |_: &Pose, _: &(), _: &Arc<Map2D>| 0.,
+            uniform_resampler(0.01, 0.01),
+        );
+        let tracking = ModeSettings {
+            max_particle_count: 200,
+            kernel_width: 1.,
+        };
+        let recovery = ModeSettings {
+            max_particle_count: 2000,
+            kernel_width: 5.,
+        };
+        let mut monitor = HealthMonitor::new(tracking, recovery, 0.5, 1., 10);
+        let mut sensor = DummySensor::new(vec![Point { x: 1., y: 0. }]).inject_faults();
+
+        for _ in 0..10 {
+            sensor.update();
+            monitor.record_reading(!sensor.sense().is_empty());
+            assert_eq!(monitor.apply(&mut mcl), LocalizationMode::Tracking);
+        }
+        assert_eq!(mcl.max_particle_count(), 200);
+
+        // the sensor stops returning data
+        sensor.dropout_rate = 1.;
+        for _ in 0..6 {
+            sensor.update();
+            monitor.record_reading(!sensor.sense().is_empty());
+            monitor.apply(&mut mcl);
+        }
+        assert!(monitor.failure_rate() > 0.5);
+        assert_eq!(monitor.mode(), LocalizationMode::Recovery);
+        assert_eq!(mcl.max_particle_count(), 2000);
+        assert_eq!(mcl.kernel_width(), 5.);
+
+        // a spread out belief also triggers recovery even with healthy sensors
+        let mut monitor = HealthMonitor::new(tracking, recovery, 0.5, 1., 10);
+        assert_eq!(monitor.update_mode(3.), LocalizationMode::Recovery);
+        assert_eq!(monitor.update_mode(0.5), LocalizationMode::Tracking);
+    }
+
+    #[test]
+    fn test_raycast_polar() {
+        use super::{
+            map::{Map2D, Object2D},
+            utility::{Point, Pose},
+        };
+
+        let map = Map2D::new(vec![
+            Object2D::Line(Point { x: 0., y: 0. }, Point { x: 10., y: 0. }),
+            Object2D::Line(Point { x: 10., y: 0. }, Point { x: 10., y: 10. }),
+            Object2D::Line(Point { x: 10., y: 10. }, Point { x: 0., y: 10. }),
+            Object2D::Triangle(
+                Point { x: 2., y: 2. },
+                Point { x: 4., y: 2. },
+                Point { x: 2., y: 5. },
+            ),
+        ]);
+        let origin = Pose {
+            angle: 0.7,
+            position: Point { x: 6., y: 5. },
+        };
+        let bearings: Vec<f64> = (0..24).map(|i| i as f64 * 0.26).collect();
+        let ranges = map.raycast_polar(origin, &bearings);
+        assert_eq!(ranges.len(), bearings.len());
+        // the left side of the map is open, so some beams miss
+        assert!(ranges.iter().any(|range| range.is_none()));
+
+        let cloud = map.raycast_cloud(origin, &bearings, None);
+        let hits: Vec<f64> = ranges.iter().filter_map(|&range| range).collect();
+        assert_eq!(hits.len(), cloud.len());
+        for (range, point) in hits.iter().zip(cloud) {
+            assert!((range - point.dist(origin.position)).abs() < 1e-9);
+        }
+        for (&bearing, range) in bearings.iter().zip(ranges) {
+            if let Some(range) = range {
+                let point = origin.position + Point::polar(origin.angle + bearing, range);
+                assert_eq!(
+                    map.raycast(origin.with_angle(origin.angle + bearing))
+                        .map(|hit| hit.dist(point) < 1e-9),
+                    Some(true)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_weight_replacement() {
+        use super::{ai::presets::*, map::*, utility::*};
+        use std::sync::Arc;
+
+        let map = square_room();
+        let parents: Vec<Pose> = (0..100)
+            .map(|i| Pose {
+                angle: 0.,
+                position: Point {
+                    x: i as f64 / 10.,
+                    y: 5.,
+                },
+            })
+            .collect();
+        // only the first 10% of the particles have a nonzero weight
+        let new_mcl = |replace_zero_weights: bool, weight_sum_threshold: f64| {
+            let mut mcl = new_test_mcl(
+                map.clone(),
+                100,
+                weight_sum_threshold,
+                |error: &f64| if *error < 1. { 1. } else { 0. },
+                |pose: &Pose, _: &(), _: &Arc<Map2D>| pose.position.x,
+                no_resampler(),
+            )
+            .with_zero_weight_replacement(replace_zero_weights);
+            mcl.belief = parents.clone();
+            mcl.observation_update(&(), &());
+            mcl
+        };
+
+        let clones = new_mcl(false, 100.);
+        assert!(clones.belief.iter().all(|pose| pose.position.x < 1.));
+
+        let replaced = new_mcl(true, 100.);
+        assert_eq!(replaced.belief.len(), 100);
+        let fresh = replaced
+            .belief
+            .iter()
+            .filter(|pose| !parents[..10].contains(pose))
+            .count();
+        assert_eq!(fresh, 90);
+        assert!(replaced.belief.iter().any(|pose| pose.position.x > 1.));
+        assert_eq!(
+            replaced
+                .last_weights()
+                .iter()
+                .filter(|&&weight| weight == 0.)
+                .count(),
+            90
+        );
+
+        // a smaller resampled belief keeps the fraction of fresh particles,
+        // and no particle with a weight of zero survives
+        let shrunk = new_mcl(true, 50.);
+        assert_eq!(shrunk.belief.len(), 50);
+        assert!(shrunk
+            .belief
+            .iter()
+            .all(|pose| !parents[10..].contains(pose)));
+        let fresh = shrunk
+            .belief
+            .iter()
+            .filter(|pose| !parents[..10].contains(pose))
+            .count();
+        assert_eq!(fresh, 45);
+    }
+
+    #[test]
+    fn test_lidar_bias_noise() {
+        use super::{
+            sensors::{dummy::DummyLidar, Sensor},
+            utility::{Point, Pose},
+        };
+        use rand_distr::Normal;
+        use std::time::Duration;
+
+        let map = square_room();
+        let robot_pose = Pose {
+            angle: 0.,
+            position: Point { x: 5., y: 5. },
+        };
+        let mut lidar = DummyLidar::new(
+            map.clone(),
+            robot_pose,
+            Normal::new(0., 0.0004).unwrap(),
+            Normal::new(0., 0.).unwrap(),
+            8,
+            Duration::from_secs(0),
+            Pose::default(),
+            None,
+        )
+        .with_bias_noise(Normal::new(0., 0.05).unwrap());
+
+        // residuals of the first two beams of each scan
+        let true_range = |bearing: f64| {
+            map.raycast(robot_pose.with_angle(bearing))
+                .unwrap()
+                .dist(robot_pose.position)
+        };
+        let residuals: Vec<(f64, f64)> = (0..300)
+            .map(|_| {
+                lidar.update_with_maps(vec![]);
+                let scan = lidar.sense();
+                (
+                    scan[0].mag() - true_range(scan[0].angle()),
+                    scan[1].mag() - true_range(scan[1].angle()),
+                )
+            })
+            .collect();
+        let correlation = |pairs: &[(f64, f64)]| {
+            let n = pairs.len() as f64;
+            let (mean_a, mean_b) = pairs.iter().fold((0., 0.), |sum, pair| {
+                (sum.0 + pair.0 / n, sum.1 + pair.1 / n)
+            });
+            let (mut cov, mut var_a, mut var_b) = (0., 0., 0.);
+            for &(a, b) in pairs {
+                cov += (a - mean_a) * (b - mean_b);
+                var_a += (a - mean_a).powi(2);
+                var_b += (b - mean_b).powi(2);
+            }
+            cov / (var_a * var_b).sqrt()
+        };
+        let within_scans = correlation(&residuals);
+        let across_scans: Vec<(f64, f64)> = residuals
+            .windows(2)
+            .map(|pair| (pair[0].0, pair[1].1))
+            .collect();
+        let across_scans = correlation(&across_scans);
+        assert!(within_scans > 0.5, "within scans: {}", within_scans);
+        assert!(across_scans.abs() < 0.3, "across scans: {}", across_scans);
+    }
+
+    #[test]
+    fn test_localizer_confidence() {
+        use super::{
+            ai::{localization::*, presets::*},
+            map::*,
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::sync::Arc;
+
+        let map = square_room();
+        let fresh: PoseMCL<_, _, _, _, (), ()> = new_test_mcl(
+            map.clone(),
+            500,
+            1.,
+            exp_weight(10.),
+            no_error,
+            uniform_resampler(0.01, 0.01),
+        );
+        let converged: PoseMCL<_, _, _, _, (), ()> = PoseMCL::from_distributions(
+            (
+                Normal::new(1., 0.01).unwrap(),
+                (
+                    Normal::new(3., 0.05).unwrap(),
+                    Normal::new(4., 0.05).unwrap(),
+                ),
+            ),
+            500,
+            1.,
+            DeathCondition {
+                particle_count_threshold: usize::MAX,
+                particle_concentration_threshold: 0.,
+            },
+            map,
+            exp_weight(10.),
+            |_: &Pose, _: &(), _: &Arc<Map2D>| 0.,
+            |_: &Pose, _: &(), _: &Arc<Map2D>| 0.,
+            uniform_resampler(0.01, 0.01),
+        );
+        let localizers: [&dyn Localizer; 2] = [&fresh, &converged];
+        for localizer in &localizers {
+            let confidence = localizer.confidence();
+            assert!(confidence > 0. && confidence <= 1.);
+        }
+        assert!(localizers[1].confidence() > localizers[0].confidence());
+        assert!(localizers[1].confidence() > 0.9);
+        assert!((localizers[1].covariance()[(1, 1)] - 0.05f64.powi(2)).abs() < 0.001);
+        assert!(
+            localizers[1]
+                .prediction()
+                .position
+                .dist(Point { x: 3., y: 4. })
+                < 0.05
+        );
+    }
+
+    #[test]
+    fn test_sample_free_point() {
+        use super::{ai::presets::*, map::*, utility::*};
+        use rand::thread_rng;
+        use std::sync::Arc;
+
+        let map = Arc::new(
+            Map2D::new(vec![Object2D::Rectangle(
+                Point { x: 0., y: 0. },
+                Point { x: 10., y: 10. },
+            )])
+            .with_obstacles(vec![Object2D::Rectangle(
+                Point { x: 2., y: 2. },
+                Point { x: 8., y: 8. },
+            )]),
+        );
+        // the outer rectangle is the walls of the map, not an obstacle
+        assert_eq!(map.obstacles.len(), 1);
+        assert_eq!(map.lines.len(), 8);
+        assert_eq!(map.labels.len(), 8);
+        assert!(map.contains(Point { x: 5., y: 5. }));
+        assert!(!map.contains(Point { x: 1., y: 5. }));
+        assert!(!map.contains(Point { x: 9., y: 9. }));
+
+        let inside_obstacle =
+            |point: Point| point.x > 2. && point.x < 8. && point.y > 2. && point.y < 8.;
+        let mut rng = thread_rng();
+        for _ in 0..1000 {
+            let point = map.sample_free_point(&mut rng).unwrap();
+            assert!(!inside_obstacle(point));
+            assert!(point.x >= 0. && point.x <= 10. && point.y >= 0. && point.y <= 10.);
+        }
+
+        let mcl = new_test_mcl(
+            map,
+            2000,
+            2000.,
+            exp_weight(1.),
+            no_error::<()>,
+            no_resampler(),
+        );
+        assert_eq!(mcl.belief.len(), 2000);
+        assert!(mcl
+            .belief
+            .iter()
+            .all(|pose| !inside_obstacle(pose.position)));
+
+        // a map which is entirely solid has no free space
+        let solid = Map2D::grid_world(1, 1, 1., &[]);
+        assert_eq!(solid.obstacles.len(), 1);
+        assert!(solid.sample_free_point(&mut rng).is_none());
+    }
+
+    #[test]
+    fn test_kalman_multi_rate() {
+        use super::ai::kalman_filter::{KalmanFilter, LocalizationFilter};
+        use nalgebra::{Matrix6, RowVector6};
+
+        let new_filter = || {
+            LocalizationFilter::new(
+                Matrix6::identity() * 0.1,
+                RowVector6::from_vec(vec![0., 0., 0., 0., 1., 0.5]),
+                LocalizationFilter::auto_tune(),
+            )
+        };
+        let close = |a: RowVector6<f64>, b: RowVector6<f64>| {
+            a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-6)
+        };
+
+        // five predictions at a higher rate cover the same time as one long prediction
+        let mut filter = new_filter();
+        for _ in 0..5 {
+            filter.prediction_update(0.1, vec![0., 0., 0.], Matrix6::zeros());
+        }
+        let mut single = new_filter();
+        single.prediction_update(0.5, vec![0., 0., 0.], Matrix6::zeros());
+        let expected = RowVector6::from_vec(vec![0., 0.5, 0.25, 0., 1., 0.5]);
+        assert!(close(filter.known_state(), expected));
+        assert!(close(single.known_state(), expected));
+
+        let measurement = RowVector6::from_vec(vec![0., 1., 0.25, 0., 1., 0.5]);
+        let r = Matrix6::identity() * 0.01;
+        filter.measurement_update(measurement, r).unwrap();
+        let corrected_x = filter.known_state()[1];
+        assert!(corrected_x > 0.5 && corrected_x < 1.);
+
+        // a second measurement without a prediction in between uses sigma points
+        // of the corrected state, the same as a new filter starting from it
+        let mut restarted = LocalizationFilter::new(
+            filter.covariance_matrix(),
+            filter.known_state(),
+            LocalizationFilter::auto_tune(),
+        );
+        filter.measurement_update(measurement, r).unwrap();
+        restarted.measurement_update(measurement, r).unwrap();
+        assert!(close(filter.known_state(), restarted.known_state()));
+        assert!(filter.known_state()[1] > corrected_x);
+        assert!(filter.covariance_matrix()[(1, 1)] < 0.1);
+    }
+
+    #[test]
+    fn test_lidar_likelihood_error() {
+        use super::{
+            ai::presets::*,
+            map::*,
+            sensors::{dummy::DummySensor, *},
+            utility::*,
+        };
+        use std::{
+            f64::{
+                consts::{E, PI},
+                INFINITY,
+            },
+            sync::Arc,
+        };
+
+        let map = Arc::new(Map2D::new(vec![Object2D::Line(
+            (10., -100.).into(),
+            (10., 100.).into(),
+        )]));
+        // five beams facing +x, the last of which hit something 2 in front of the wall
+        let mut scan = vec![Point::polar(PI, 10.); 4];
+        scan.push(Point::polar(PI, 8.));
+        let lidar = DummySensor::new(scan).override_limit(Some(0.0..INFINITY));
+        // one beam off by 2
+        let one_outlier = Pose {
+            angle: 0.,
+            position: (0., 0.).into(),
+        };
+        // every beam off by 0.5 or 1.5
+        let all_off = Pose {
+            angle: 0.,
+            position: (0.5, 0.).into(),
+        };
+
+        // the summed error prefers one large error to several small ones...
+        let summed = lidar_error(1., 1.);
+        assert!(summed(&one_outlier, &lidar, &map) < summed(&all_off, &lidar, &map));
+        // ...but the product of the beam likelihoods is larger with several small errors
+        let likelihood = lidar_likelihood_error(1., 0.01);
+        let (outlier_error, all_off_error) = (
+            likelihood(&one_outlier, &lidar, &map),
+            likelihood(&all_off, &lidar, &map),
+        );
+        assert!(all_off_error < outlier_error);
+        // 2² / 2 = 2 against 4 * 0.5² / 2 + 1.5² / 2 = 1.625
+        assert!((outlier_error - all_off_error - 0.375).abs() < 1e-9);
+
+        // with a narrow kernel every likelihood underflows, but is still relative to the best
+        let narrow = lidar_likelihood_error(0.02, 0.01);
+        assert_eq!(E.powf(-narrow(&all_off, &lidar, &map)), 0.);
+        let mut mcl = new_test_mcl(map.clone(), 2, 2., exp_weight(E), narrow, no_resampler())
+            .with_log_likelihood(true);
+        mcl.belief = vec![one_outlier, all_off];
+        mcl.observation_update(&lidar, &());
+        assert!(mcl.belief.iter().all(|&pose| pose == all_off));
+    }
+
+    #[test]
+    fn test_raycast_jittered() {
+        use super::{
+            map::{Map2D, Object2D},
+            utility::{Point, Pose},
+        };
+
+        // a wall with a thin column in front of it
+        let map = Map2D::new(vec![
+            Object2D::Line((10., -10.).into(), (10., 10.).into()),
+            Object2D::Rectangle((5., -0.05).into(), (5.1, 0.05).into()),
+        ]);
+        let poses: Vec<Pose> = (0..=100)
+            .map(|i| Pose {
+                angle: 0.,
+                position: Point {
+                    x: 0.,
+                    y: -0.5 + i as f64 / 100.,
+                },
+            })
+            .collect();
+        let max_step = |dists: Vec<f64>| {
+            dists
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .fold(0., f64::max)
+        };
+
+        let plain: Vec<f64> = poses
+            .iter()
+            .map(|&pose| map.raycast(pose).unwrap().dist(pose.position))
+            .collect();
+        assert!(max_step(plain) > 4.9);
+
+        let jittered: Vec<f64> = poses
+            .iter()
+            .map(|&pose| {
+                map.raycast_jittered(pose, 0.25, 11)
+                    .unwrap()
+                    .dist(pose.position)
+            })
+            .collect();
+        // the column is hit by some of the rays from y = -0.3 to 0.3
+        assert!(jittered[50] < 10. && jittered[50] > 5.);
+        assert_eq!(jittered[0], 10.);
+        // at most one ray on each side of the column flips per step
+        assert!(max_step(jittered) <= 2. * 4.9 / 11. + 1e-9);
+
+        let pose = poses[50];
+        assert_eq!(map.raycast_jittered(pose, 0.25, 1), map.raycast(pose));
+    }
+
+    #[test]
+    #[cfg(feature = "render")]
+    fn test_scan_ray_endpoints() {
+        use super::{replay::render::scan_ray_endpoints, utility::*};
+        use std::f64::consts::FRAC_PI_2;
+
+        let origin = Pose {
+            angle: FRAC_PI_2,
+            position: (1., 2.).into(),
+        };
+        let bearings = [0., -FRAC_PI_2, FRAC_PI_2];
+        let endpoints = scan_ray_endpoints(
+            origin,
+            &[Some(3.), None, Some(1.)],
+            &bearings,
+            10.,
+            (5., 5.).into(),
+        );
+        // the beam without a range is skipped
+        assert_eq!(endpoints.len(), 2);
+        let start = Point { x: 15., y: 25. };
+        assert!(endpoints.iter().all(|&(ray_start, _)| ray_start == start));
+        // facing +y, 3 away
+        assert!(endpoints[0].1.dist(Point { x: 15., y: 55. }) < 1e-9);
+        // facing -x, 1 away
+        assert!(endpoints[1].1.dist(Point { x: 5., y: 25. }) < 1e-9);
+    }
+
+    #[test]
+    fn test_sweep_particle_count() {
+        use super::{
+            ai::{localization::*, presets::*},
+            harness::{position_rmse, sweep, Simulation},
+            map::*,
+            sensors::dummy::{DummyLidar, DummyPositionSensor},
+            utility::*,
+        };
+        use rand::{rngs::StdRng, SeedableRng};
+        use rand_distr::{Distribution, Normal};
+        use std::{sync::Arc, time::Duration};
+
+        let true_pose = Pose {
+            angle: 1.,
+            position: Point { x: 7., y: 3. },
+        };
+        assert_eq!(position_rmse(&[(true_pose, true_pose)]), 0.);
+        assert_eq!(
+            position_rmse(&[
+                (
+                    true_pose,
+                    Pose {
+                        position: true_pose.position + Point { x: 3., y: 0. },
+                        ..true_pose
+                    }
+                ),
+                (
+                    true_pose,
+                    Pose {
+                        position: true_pose.position + Point { x: 0., y: -1. },
+                        ..true_pose
+                    }
+                ),
+            ]),
+            5f64.sqrt()
+        );
+
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle(Point { x: 0., y: 0. }, Point { x: 10., y: 10. }),
+            Object2D::Triangle(
+                Point { x: 2., y: 2. },
+                Point { x: 4., y: 2. },
+                Point { x: 2., y: 5. },
+            ),
+        ]));
+        // sensor and resampling noise aren't seeded, so compare the mean RMSE over several seeds
+        let seeds = 8;
+        let mut mean_rmses = vec![0.; 3];
+        for seed in 0..seeds {
+            let results = sweep(
+                vec![3, 100, 1000],
+                |&particles| {
+                    let lidar = DummyLidar::new(
+                        map.clone(),
+                        true_pose,
+                        Normal::new(0., 0.0001).unwrap(),
+                        Normal::new(0., 0.001).unwrap(),
+                        36,
+                        Duration::from_secs(0),
+                        Pose::default(),
+                        None,
+                    );
+                    let motion_sensor = DummyPositionSensor::new(true_pose, Pose::default());
+                    let mut mcl: PoseMCL<_, _, _, _, _, ()> = new_test_mcl(
+                        map.clone(),
+                        particles,
+                        particles as f64,
+                        exp_weight(3.),
+                        lidar_error(1., 1.),
+                        uniform_resampler(0.01, 0.02),
+                    );
+                    // start tracking from a rough estimate of the pose, where the smaller
+                    // beliefs are the first particles of the larger ones
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    let (angle_noise, position_noise) =
+                        (Normal::new(0., 0.3).unwrap(), Normal::new(0., 2.).unwrap());
+                    mcl.belief = (0..particles)
+                        .map(|_| Pose {
+                            angle: true_pose.angle + angle_noise.sample(&mut rng),
+                            position: true_pose.position
+                                + Point {
+                                    x: position_noise.sample(&mut rng),
+                                    y: position_noise.sample(&mut rng),
+                                },
+                        })
+                        .collect();
+                    Simulation::new(|_| true_pose, 0.1, lidar, motion_sensor, mcl)
+                },
+                20,
+                seed,
+            );
+            assert_eq!(
+                results
+                    .iter()
+                    .map(|&(particles, _)| particles)
+                    .collect::<Vec<_>>(),
+                vec![3, 100, 1000]
+            );
+            for (mean_rmse, (_, rmse)) in mean_rmses.iter_mut().zip(results) {
+                *mean_rmse += rmse / seeds as f64;
+            }
+        }
+        assert!(
+            mean_rmses.windows(2).all(|pair| pair[1] < 0.8 * pair[0]),
+            "mean RMSE should decrease with more particles: {:?}",
+            mean_rmses
+        );
+    }
+
+    #[test]
+    fn test_raycast_outcome() {
+        use super::{
+            map::{Map2D, Object2D, RaycastOutcome},
+            utility::{Point, Pose},
+        };
+        use std::f64::consts::PI;
+
+        let map = Map2D::new(vec![Object2D::Rectangle(
+            Point { x: 0., y: 0. },
+            Point { x: 10., y: 10. },
+        )]);
+        // in open space, 9 away from the wall it is facing
+        let inside = Pose {
+            angle: 0.,
+            position: Point { x: 1., y: 5. },
+        };
+        match map.raycast_outcome(inside, 5.) {
+            RaycastOutcome::BeyondRange(hit) => assert!((hit.dist - 9.).abs() < 1e-9),
+            outcome => panic!("expected a hit beyond range, got {:?}", outcome),
+        }
+        match map.raycast_outcome(inside, 10.) {
+            RaycastOutcome::Hit(hit) => assert!((hit.dist - 9.).abs() < 1e-9),
+            outcome => panic!("expected a hit, got {:?}", outcome),
+        }
+        // outside of the map, facing away from it
+        let outside = Pose {
+            angle: PI,
+            position: Point { x: -3., y: 5. },
+        };
+        assert_eq!(map.raycast_outcome(outside, 5.), RaycastOutcome::LeftMap);
+    }
+
+    #[test]
+    fn test_normalized_weights() {
+        use super::{ai::presets::*, map::*, utility::*};
+        use std::sync::Arc;
+
+        let map = square_room();
+        let mut mcl = new_test_mcl(
+            map,
+            200,
+            200.,
+            exp_weight(2.),
+            |pose: &Pose, _: &(), _: &Arc<Map2D>| pose.position.x,
+            no_resampler(),
+        );
+        let sum = |weights: &[f64]| weights.iter().sum::<f64>();
+        for _ in 0..3 {
+            mcl.observation_update(&(), &());
+            assert_eq!(mcl.last_weights().len(), mcl.belief.len());
+            assert!((sum(mcl.last_weights()) - 1.).abs() < 1e-9);
+        }
+        mcl.inject_particles(&[Pose::default()], 0.1);
+        assert!((sum(mcl.last_weights()) - 1.).abs() < 1e-9);
+        let summary = mcl.belief_summary();
+        assert!(summary.effective_sample_size > 1.);
+        assert!(summary.effective_sample_size <= mcl.belief.len() as f64 + 1e-9);
+    }
+
+    #[test]
+    fn test_generated_maps() {
+        use super::{
+            map::Map2D,
+            utility::{Point, Pose},
+        };
+        use std::f64::consts::FRAC_PI_2;
+
+        // a plus shaped maze with solid corners
+        let open = [(0, 1), (1, 0), (1, 1), (1, 2), (2, 1)];
+        let map = Map2D::grid_world(3, 3, 2., &open);
+        assert_eq!(map.size, Point { x: 6., y: 6. });
+        // the outer walls and the four sides of each solid cell
+        assert_eq!(map.lines.len(), 4 + 4 * 4);
+        assert_eq!(map.obstacles.len(), 4);
+        assert!(map.contains(Point { x: 1., y: 1. }));
+        assert!(!map.contains(Point { x: 3., y: 1. }));
+
+        // down the open middle row from the center of its first cell
+        let start = Pose {
+            angle: 0.,
+            position: Point { x: 1., y: 3. },
+        };
+        let hit = map.raycast(start).unwrap();
+        assert!((hit.dist(start.position) - 5.).abs() < 1e-9);
+        // into the solid corner cell above it
+        let hit = map.raycast(start.with_angle(FRAC_PI_2)).unwrap();
+        assert!((hit.dist(start.position) - 1.).abs() < 1e-9);
+
+        let corridor = Map2D::corridor(20., 1.);
+        assert_eq!(corridor.size, Point { x: 20., y: 1. });
+        assert_eq!(corridor.lines.len(), 4);
+        assert!(corridor.obstacles.is_empty());
+        let start = Pose {
+            angle: 0.,
+            position: Point { x: 0.5, y: 0.5 },
+        };
+        let hit = corridor.raycast(start).unwrap();
+        assert!((hit.dist(start.position) - 19.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_observation_update_with_predictions() {
+        use super::{
+            ai::{localization::*, presets::*},
+            map::*,
+            sensors::{dummy::DummyLidar, Sensor},
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::{sync::Arc, time::Duration};
+
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle(Point { x: 0., y: 0. }, Point { x: 10., y: 10. }),
+            Object2D::Triangle(
+                Point { x: 2., y: 2. },
+                Point { x: 4., y: 2. },
+                Point { x: 2., y: 5. },
+            ),
+        ]));
+        let true_pose = Pose {
+            angle: 1.,
+            position: Point { x: 7., y: 6. },
+        };
+        let mut lidar = DummyLidar::new(
+            map.clone(),
+            true_pose,
+            Normal::new(0., 0.01).unwrap(),
+            Normal::new(0., 0.).unwrap(),
+            24,
+            Duration::from_secs(0),
+            Pose::default(),
+            None,
+        );
+        lidar.update_with_maps(vec![]);
+        let scan = lidar.sense();
+        let belief: Vec<Pose> = (0..50)
+            .map(|i| Pose {
+                angle: 1. + (i % 5) as f64 * 0.1,
+                position: Point {
+                    x: 5. + (i / 5) as f64 * 0.3,
+                    y: 6.,
+                },
+            })
+            .collect();
+        let new_mcl = || {
+            let mut mcl: PoseMCL<_, _, _, _, _, ()> = new_test_mcl(
+                map.clone(),
+                50,
+                50.,
+                exp_weight(2.),
+                lidar_error(1., 1.),
+                no_resampler(),
+            )
+            .with_seed(4904);
+            mcl.belief = belief.clone();
+            mcl
+        };
+
+        let mut raycasting = new_mcl();
+        raycasting.observation_update(&lidar, &());
+
+        let real: Vec<Option<f64>> = scan.iter().map(|point| Some(point.mag())).collect();
+        let bearings: Vec<f64> = scan.iter().map(|point| point.angle()).collect();
+        let predicted: Vec<Vec<Option<f64>>> = belief
+            .iter()
+            .map(|&pose| map.raycast_polar(pose, &bearings))
+            .collect();
+        let mut predicting = new_mcl();
+        predicting.observation_update_with_predictions(&real, &predicted, range_scan_error(1., 1.));
+
+        // the same weights give the same draws with the same seed
+        assert_eq!(raycasting.belief, predicting.belief);
+        for (a, b) in raycasting
+            .last_weights()
+            .iter()
+            .zip(predicting.last_weights())
+        {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_map_shared_between_threads() {
+        use super::{
+            map::{Map2D, Object2D},
+            utility::{Point, Pose},
+        };
+        use std::{f64::consts::PI, sync::Arc, thread};
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Map2D>();
+
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle(Point { x: 0., y: 0. }, Point { x: 10., y: 10. }),
+            Object2D::Triangle(
+                Point { x: 2., y: 2. },
+                Point { x: 4., y: 2. },
+                Point { x: 2., y: 5. },
+            ),
+        ]));
+        let poses: Vec<Pose> = (0..64)
+            .map(|i| Pose {
+                angle: i as f64 * PI / 32.,
+                position: Point { x: 6., y: 6. },
+            })
+            .collect();
+        let expected: Vec<Option<Point>> = poses.iter().map(|&pose| map.raycast(pose)).collect();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (map, poses) = (map.clone(), poses.clone());
+                thread::spawn(move || {
+                    (0..100)
+                        .map(|_| poses.iter().map(|&pose| map.raycast(pose)).collect())
+                        .collect::<Vec<Vec<Option<Point>>>>()
+                })
+            })
+            .collect();
+        for handle in handles {
+            for hits in handle.join().unwrap() {
+                assert_eq!(hits, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pose_distance() {
+        use super::utility::{Point, Pose};
+        use std::f64::consts::PI;
+
+        let pose = Pose {
+            angle: 0.5,
+            position: Point { x: 1., y: 2. },
+        };
+        let turned = pose.with_angle(0.5 + PI);
+        assert!((pose.angle_dist(turned) - PI).abs() < 1e-9);
+        assert!((pose.distance_to(turned, 0.3) - 0.3 * PI).abs() < 1e-9);
+        assert_eq!(pose.distance_to(turned, 0.), 0.);
+
+        // the angle takes the shortest arc, across 0 and whole turns
+        let wrapped = pose.with_angle(0.5 - 0.1 + 4. * PI);
+        assert!((pose.angle_dist(wrapped) - 0.1).abs() < 1e-9);
+        assert!((wrapped.angle_dist(pose) - 0.1).abs() < 1e-9);
+
+        let moved = Pose {
+            angle: 0.5 + 0.4,
+            position: Point { x: 4., y: 6. },
+        };
+        assert!((pose.distance_to(moved, 0.) - 5.).abs() < 1e-9);
+        // 5 and 10 * 0.4 combine like the sides of a right triangle
+        assert!((pose.distance_to(moved, 10.) - 41f64.sqrt()).abs() < 1e-9);
+        assert!((pose.distance_to(moved, 10.) - moved.distance_to(pose, 10.)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_kalman_singular_innovation() {
+        use super::ai::kalman_filter::{KalmanFilter, LocalizationFilter};
+        use nalgebra::{Matrix6, RowVector6};
+
+        // with a certain state and a measurement with no noise in its last component,
+        // the innovation covariance has a zero on its diagonal
+        let mut filter = LocalizationFilter::new(
+            Matrix6::zeros(),
+            RowVector6::zeros(),
+            LocalizationFilter::auto_tune(),
+        );
+        let mut r = Matrix6::identity() * 0.01;
+        r[(5, 5)] = 0.;
+        filter
+            .measurement_update(RowVector6::from_vec(vec![0.1, 0.2, 0.3, 0., 0., 0.]), r)
+            .unwrap();
+        assert!(filter.known_state().iter().all(|v| v.is_finite()));
+        assert!(filter.covariance_matrix().iter().all(|v| v.is_finite()));
+
+        // a measurement which isn't finite can't be recovered from
+        let state = filter.known_state();
+        assert!(filter
+            .measurement_update(
+                RowVector6::from_element(f64::NAN),
+                Matrix6::from_element(f64::NAN)
+            )
+            .is_err());
+        assert_eq!(filter.known_state(), state);
+    }
+
+    #[test]
+    fn test_nearest_wall() {
+        use super::{ai::presets::*, map::*, utility::*};
+        use std::sync::Arc;
+
+        let new_mcl = |map: Map2D, position: Point| {
+            let mut mcl = new_test_mcl(
+                Arc::new(map),
+                10,
+                10.,
+                exp_weight(2.),
+                no_error::<()>,
+                no_resampler(),
+            );
+            mcl.belief = vec![
+                Pose {
+                    angle: 0.,
+                    position,
+                };
+                10
+            ];
+            mcl
+        };
+        let walls = || {
+            Map2D::new(vec![
+                Object2D::Line(Point { x: 0., y: 0. }, Point { x: 10., y: 0. }),
+                Object2D::Line(Point { x: 10., y: 0. }, Point { x: 10., y: 10. }),
+            ])
+        };
+
+        // to the left of the first wall, looking along it
+        let (line, dist) = new_mcl(walls(), Point { x: 4., y: 1. })
+            .nearest_wall()
+            .unwrap();
+        assert_eq!(line, 0);
+        assert!((dist - 1.).abs() < 1e-9);
+        // to the right of it
+        let (line, dist) = new_mcl(walls(), Point { x: 4., y: -2. })
+            .nearest_wall()
+            .unwrap();
+        assert_eq!(line, 0);
+        assert!((dist + 2.).abs() < 1e-9);
+        // to the left of the second wall
+        let (line, dist) = new_mcl(walls(), Point { x: 9.5, y: 5. })
+            .nearest_wall()
+            .unwrap();
+        assert_eq!(line, 1);
+        assert!((dist - 0.5).abs() < 1e-9);
+
+        assert!(new_mcl(Map2D::new(vec![]), Point { x: 1., y: 1. })
+            .nearest_wall()
+            .is_none());
+    }
+
+    #[test]
+    fn test_labeled_lidar_error() {
+        use super::{
+            ai::presets::*,
+            map::*,
+            sensors::{dummy::DummySensor, *},
+            utility::*,
+        };
+        use std::{
+            collections::HashMap,
+            f64::{consts::PI, INFINITY},
+            sync::Arc,
+        };
+
+        const GLASS: RegionId = 0;
+        // the wall along x = 10 is glass, which the lidar may see through
+        let map = Map2D::new(vec![Object2D::Rectangle(
+            (0., 0.).into(),
+            (10., 10.).into(),
+        )])
+        .with_label(2, GLASS);
+        assert_eq!(map.labels, vec![None, None, Some(GLASS), None]);
+        let map = Arc::new(map);
+        let pose = Pose {
+            angle: 0.,
+            position: (5., 5.).into(),
+        };
+        // a beam facing the glass wall and a beam facing the opposite wall, both 2 short
+        let at_glass =
+            DummySensor::new(vec![Point::polar(PI, 3.)]).override_limit(Some(0.0..INFINITY));
+        let at_wall =
+            DummySensor::new(vec![Point::polar(0., 3.)]).override_limit(Some(0.0..INFINITY));
+
+        let unlabeled = lidar_error(1., 1.);
+        assert!((unlabeled(&pose, &at_glass, &map) - 2.).abs() < 1e-9);
+        assert!((unlabeled(&pose, &at_wall, &map) - 2.).abs() < 1e-9);
+        let mut reliability = HashMap::new();
+        reliability.insert(GLASS, 0.1);
+        let labeled = labeled_lidar_error(1., 1., reliability);
+        let (glass_error, wall_error) = (
+            labeled(&pose, &at_glass, &map),
+            labeled(&pose, &at_wall, &map),
+        );
+        assert!(glass_error < wall_error);
+        assert!((glass_error - 0.2).abs() < 1e-9);
+        assert!((wall_error - 2.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_simulation_dt_schedule() {
+        use super::{
+            ai::{
+                kalman_filter::{KalmanFilter, LocalizationFilter},
+                localization::*,
+                presets::*,
+            },
+            harness::{is_converged, Simulation},
+            map::*,
+            sensors::dummy::{DummyLidar, DummyPositionSensor},
+            utility::*,
+        };
+        use nalgebra::{Matrix6, RowVector6, Vector6};
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+        use rand_distr::Normal;
+        use std::{f64::consts::FRAC_PI_2, sync::Arc, time::Duration};
+        let map = Arc::new(Map2D::new(vec![
+            Object2D::Rectangle((0., 0.).into(), (20., 10.).into()),
+            Object2D::Line((14., 0.).into(), (14., 3.).into()),
+        ]));
+        let trajectory = |t: f64| Pose {
+            angle: t + FRAC_PI_2,
+            position: Point { x: 8., y: 5. } + Point::polar(t, 3.),
+        };
+        let start = trajectory(0.);
+        // steps jitter between 0.01 and 0.1 seconds
+        let mut rng = StdRng::seed_from_u64(4904);
+        let dts: Vec<f64> = (0..100).map(|_| rng.gen_range(0.01..0.1)).collect();
+        let new_simulation = || {
+            let lidar = DummyLidar::new(
+                map.clone(),
+                start,
+                Normal::new(0., 0.0001).unwrap(),
+                Normal::new(0., 0.001).unwrap(),
+                36,
+                Duration::from_secs(0),
+                Pose::default(),
+                None,
+            );
+            let motion_sensor = DummyPositionSensor::new(
+                start,
+                Pose {
+                    angle: 0.01,
+                    position: (0.05, 0.05).into(),
+                },
+            );
+            let mcl: PoseMCL<_, _, _, _, _, ()> = PoseMCL::from_distributions(
+                (
+                    Normal::new(start.angle, 0.1).unwrap(),
+                    (
+                        Normal::new(start.position.x, 0.5).unwrap(),
+                        Normal::new(start.position.y, 0.5).unwrap(),
+                    ),
+                ),
+                1000,
+                1000.,
+                DeathCondition {
+                    particle_count_threshold: usize::MAX,
+                    particle_concentration_threshold: 0.,
+                },
+                map.clone(),
+                exp_weight(10.),
+                lidar_error(1., 1.),
+                |_: &Pose, _: &(), _: &Arc<Map2D>| 0.,
+                uniform_resampler(0.02, 0.05),
+            );
+            let mut schedule = dts.clone().into_iter();
+            Simulation::new(trajectory, 0.05, lidar, motion_sensor, mcl)
+                .with_dt_schedule(move || schedule.next().unwrap())
+        };
+
+        let mut sim = new_simulation();
+        let results = sim.run(100);
+        assert!((sim.time() - dts.iter().sum::<f64>()).abs() < 1e-9);
+        assert_eq!(sim.delta_t, dts[99]);
+        let (true_pose, prediction) = results[99];
+        assert!(is_converged(true_pose, prediction, 0.25, 0.1));
+        let mean_error = results
+            .iter()
+            .map(|(true_pose, prediction)| true_pose.position.dist(prediction.position))
+            .sum::<f64>()
+            / 100.;
+        assert!(mean_error < 0.25, "mean position error {}", mean_error);
+
+        // a filter predicting by the scheduled time deltas keeps up with the localizer,
+        // stopping before the angle wraps around
+        let filter = LocalizationFilter::new(
+            Matrix6::from_diagonal(&Vector6::from_vec(vec![0.01, 0.25, 0.25, 0.01, 0.01, 0.01])),
+            RowVector6::from_vec(vec![
+                start.angle,
+                start.position.x,
+                start.position.y,
+                1.,
+                0.,
+                3.,
+            ]),
+            LocalizationFilter::auto_tune(),
+        );
+        let mut sim = new_simulation().with_filter(
+            filter,
+            Matrix6::from_diagonal(&Vector6::from_vec(vec![0.01, 0.01, 0.01, 1., 9., 9.])),
+            Matrix6::identity() * 0.01,
+        );
+        let results = sim.run(50);
+        assert!((sim.time() - dts[..50].iter().sum::<f64>()).abs() < 1e-9);
+        let (true_pose, _) = results[49];
+        let filter_prediction = sim.filter().unwrap().prediction();
+        assert!(
+            filter_prediction.position.dist(true_pose.position) < 0.25,
+            "filter predicted {:?} at {:?}",
+            filter_prediction,
+            true_pose
+        );
+    }
+
+    #[test]
+    fn test_open_world_raycast() {
+        use super::{
+            ai::{localization::*, presets::*},
+            map::*,
+            sensors::{dummy::DummyLidar, Sensor},
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::{f64::consts::PI, sync::Arc, time::Duration};
+
+        let wall = || vec![Object2D::Line((10., -100.).into(), (10., 100.).into())];
+        let true_pose = Pose {
+            angle: 0.,
+            position: (3., 0.).into(),
+        };
+        let closed = Map2D::new(wall());
+        assert_eq!(closed.raycast_distance(true_pose.with_angle(PI)), None);
+        let map = Arc::new(Map2D::new(wall()).with_open_world(true));
+        assert!((map.raycast_distance(true_pose).unwrap() - 7.).abs() < 1e-9);
+        // an unobstructed ray has an infinite range rather than none
+        assert_eq!(
+            map.raycast_distance(true_pose.with_angle(PI)),
+            Some(f64::INFINITY)
+        );
+
+        let mut lidar = DummyLidar::new(
+            map.clone(),
+            true_pose,
+            Normal::new(0., 0.0001).unwrap(),
+            Normal::new(0., 0.001).unwrap(),
+            36,
+            Duration::from_secs(0),
+            Pose::default(),
+            None,
+        );
+        lidar.update();
+        // only the beams facing the wall get a return
+        let scan = lidar.sense();
+        assert!(!scan.is_empty() && scan.len() < 36);
+        assert!(scan.iter().all(|point| point.mag().is_finite()));
+
+        let mut mcl: PoseMCL<_, _, _, _, DummyLidar, ()> = new_test_mcl(
+            map,
+            50,
+            50.,
+            exp_weight(2.),
+            lidar_error(1., 1.),
+            no_resampler(),
+        )
+        .with_seed(4904);
+        mcl.belief = (0..50)
+            .map(|i| Pose {
+                angle: 0.,
+                position: (1. + i as f64 * 0.16, 0.).into(),
+            })
+            .collect();
+        mcl.observation_update(&lidar, &());
+        assert!(mcl.last_weights().iter().all(|weight| weight.is_finite()));
+        assert!(mcl.belief.iter().all(|particle| particle.is_finite()));
+        let prediction = mcl.get_prediction();
+        assert!(prediction.is_finite());
+        assert!((prediction.position.x - 3.).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_health_report() {
+        use super::{
+            ai::{localization::*, presets::*},
+            sensors::dummy::DummyLidar,
+            utility::*,
+        };
+        use std::f64::NAN;
+
+        let map = square_room();
+        let new_mcl = || {
+            let mut mcl: PoseMCL<_, _, _, _, DummyLidar, ()> = new_test_mcl(
+                map.clone(),
+                100,
+                100.,
+                exp_weight(10.),
+                lidar_error(1., 1.),
+                no_resampler(),
+            )
+            .with_seed(4904);
+            mcl.belief = (0..100)
+                .map(|i| Pose {
+                    angle: 0.,
+                    position: (1. + (i % 10) as f64 * 0.8, 1. + (i / 10) as f64 * 0.8).into(),
+                })
+                .collect();
+            mcl
+        };
+        let real = [Some(5.)];
+
+        // evenly weighted particles are resampled into a healthy belief
+        let mut mcl = new_mcl();
+        let report = mcl.health_report();
+        assert_eq!(report.unique_ancestor_fraction, None);
+        assert!(report.is_healthy());
+        let exact = vec![vec![Some(5.)]; 100];
+        mcl.observation_update_with_predictions(&real, &exact, range_scan_error(1., 1.));
+        let report = mcl.health_report();
+        assert!((report.effective_sample_fraction.unwrap() - 1.).abs() < 1e-9);
+        assert!(report.unique_ancestor_fraction.unwrap() > 0.5);
+        assert!(report.is_healthy(), "{:?}", report);
+
+        // one particle has all of the weight, so the belief is made of copies of it
+        let mut mcl = new_mcl();
+        let mut predicted = vec![vec![Some(50.)]; 100];
+        predicted[0] = vec![Some(5.)];
+        mcl.observation_update_with_predictions(&real, &predicted, range_scan_error(1., 1.));
+        let report = mcl.health_report();
+        assert!(report.degenerate);
+        assert!(report.impoverished);
+        assert!(!report.out_of_bounds && !report.nan_contaminated);
+        assert_eq!(report.remedies.len(), 2);
+
+        // particles which drifted out of the map
+        let mut mcl = new_mcl();
+        for particle in mcl.belief.iter_mut().take(20) {
+            particle.position.x += 20.;
+        }
+        let report = mcl.health_report();
+        assert_eq!(report.out_of_bounds_count, 20);
+        assert!(report.out_of_bounds);
+        assert!(!report.impoverished && !report.degenerate && !report.nan_contaminated);
+
+        // a single NaN particle contaminates the belief
+        let mut mcl = new_mcl();
+        mcl.belief[0].angle = NAN;
+        let report = mcl.health_report();
+        assert_eq!(report.non_finite_count, 1);
+        assert!(report.nan_contaminated);
+        assert!(!report.out_of_bounds);
+        assert_eq!(report.remedies.len(), 1);
+    }
+
+    #[test]
+    fn test_raycast_after_map_edit() {
+        use super::{map::*, utility::*};
+
+        let map = Map2D::new(vec![Object2D::Rectangle(
+            Point { x: 0., y: 0. },
+            Point { x: 10., y: 10. },
+        )]);
+        let start = Pose {
+            angle: 0.,
+            position: Point { x: 1., y: 5. },
+        };
+        assert_eq!(map.raycast(start), Some(Point { x: 10., y: 5. }));
+
+        // there is no cache, so the very next raycast sees an added obstacle
+        let mut map = map.with_obstacles(vec![Object2D::Rectangle(
+            Point { x: 4., y: 4. },
+            Point { x: 6., y: 6. },
+        )]);
+        assert_eq!(map.raycast(start), Some(Point { x: 4., y: 5. }));
+
+        // as well as lines pushed onto the map directly
+        map.vertices.push(Point { x: 2., y: 0. });
+        map.vertices.push(Point { x: 2., y: 10. });
+        map.lines
+            .push((map.vertices.len() - 2, map.vertices.len() - 1));
+        map.labels.push(None);
+        let hit = map.raycast_hit(start).unwrap();
+        assert_eq!(hit.point, Point { x: 2., y: 5. });
+        assert_eq!(hit.line, Some(map.lines.len() - 1));
+    }
+
+    #[test]
+    fn test_mcl_on_sdf_map() {
+        use super::{
+            ai::{localization::*, presets::*},
+            map::*,
+            sensors::{dummy::DummyLidar, Sensor},
+            utility::*,
+        };
+        use rand_distr::Normal;
+        use std::{sync::Arc, time::Duration};
+
+        type SdfMap = MapSdf<fn(Point) -> f64>;
+        // a 10 by 10 room with a round pillar of radius 1 at (6, 5)
+        fn sdf(point: Point) -> f64 {
+            let walls = point.x.min(point.y).min(10. - point.x).min(10. - point.y);
+            walls.min(point.dist(Point { x: 6., y: 5. }) - 1.)
+        }
+        let map = Arc::new(
+            MapSdf::new(Point { x: 10., y: 10. }, sdf as fn(Point) -> f64)
+                .with_raymarch_params(1000, 1e-6),
+        );
+        let true_pose = Pose {
+            angle: 0.5,
+            position: Point { x: 2., y: 3. },
+        };
+        let mut lidar = DummyLidar::new(
+            map.clone(),
+            true_pose,
+            Normal::new(0., 0.0001).unwrap(),
+            Normal::new(0., 0.001).unwrap(),
+            36,
+            Duration::from_secs(0),
+            Pose::default(),
+            None,
+        );
+        lidar.update();
+        assert_eq!(lidar.sense().len(), 36);
+        // the beam straight ahead of the lidar hits the near side of the pillar
+        let to_center = Point { x: 6., y: 5. } - true_pose.position;
+        let direction = Point::polar(true_pose.angle, 1.);
+        let expected =
+            to_center.dot(direction) - (1. - to_center.cross_mag(direction).powi(2)).sqrt();
+        assert!((map.raycast_distance(true_pose).unwrap() - expected).abs() < 1e-4);
+
+        let mut mcl: PoseMCL<_, _, _, _, DummyLidar<SdfMap>, (), _> = PoseMCL::new(
+            100,
+            100.,
+            DeathCondition {
+                particle_count_threshold: usize::MAX,
+                particle_concentration_threshold: 0.,
+            },
+            map,
+            exp_weight(10.),
+            lidar_error(1., 1.),
+            |_: &Pose, _: &(), _: &Arc<SdfMap>| 0.,
+            no_resampler(),
+        )
+        .with_seed(4904);
+        // particles are spread over the free space of the map
+        assert!(mcl.belief.iter().all(|pose| sdf(pose.position) >= 0.));
+        mcl.belief[0] = true_pose;
+        mcl.observation_update(&lidar, &());
+        let weights = mcl.last_weights();
+        let best = (0..weights.len())
+            .max_by(|&a, &b| weights[a].total_cmp(&weights[b]))
+            .unwrap();
+        assert_eq!(mcl.belief[best], true_pose);
+    }
+
+    #[test]
+    fn test_summary_effective_sample_size() {
+        use super::{ai::presets::*, map::*, utility::*};
+        use std::sync::Arc;
+
+        let map = square_room();
+        let target = Point { x: 5., y: 5. };
+        let mut mcl = new_test_mcl(
+            map,
+            100,
+            100.,
+            exp_weight(10.),
+            move |pose: &Pose, _: &(), _: &Arc<Map2D>| 10. * pose.position.dist(target),
+            no_resampler(),
+        )
+        .with_seed(4904);
+        // one particle on the target, and the rest at least 1 away from it
+        mcl.belief = (0..100)
+            .map(|i| Pose {
+                angle: 0.,
+                position: target + Point::polar(i as f64, (i.min(1) + i % 3) as f64),
+            })
+            .collect();
+        mcl.observation_update(&(), &());
+        // the resampled belief is (almost) all copies of the one particle with all the weight
+        let summary = mcl.belief_summary();
+        assert!(summary.effective_sample_size < 1.01);
+        let fraction = mcl.health_report().effective_sample_fraction.unwrap();
+        assert!((summary.effective_sample_size - fraction * 100.).abs() < 1e-9);
+    }
 }
@@ -1,4 +1,5 @@
 use crate::utility::{Point, Point3D, Pose, Pose3D};
+use rand::Rng;
 use std::{f64::{consts::PI, INFINITY}, sync::Arc};
 
 // TODO: this file is lazy
@@ -6,18 +7,69 @@ use std::{f64::{consts::PI, INFINITY}, sync::Arc};
 pub enum Object2D {
     Target(Pose3D),
     Line(Point, Point),
+    /// A line which reflects rays in `Map2D::raycast_reflective`
+    Mirror(Point, Point),
     Triangle(Point, Point, Point),
     Rectangle(Point, Point),
     RectangleFour(Point, Point, Point, Point),
 }
 
+/// Number of rejected samples after which `Raycaster::sample_free_point` gives up
+pub const MAX_FREE_POINT_ATTEMPTS: usize = 1000;
+
+/// Tolerance used by raycasts for ties between hits and for hits at the ends of lines
+pub const RAYCAST_TIE_EPSILON: f64 = 1e-9;
+
+/// `RAYCAST_TIE_EPSILON` for `Map2DF32`, which is larger as `f32`s are much less precise
+#[cfg(feature = "f32")]
+pub const RAYCAST_TIE_EPSILON_F32: f32 = 1e-5;
+
+/// The closest intersection of a ray with a map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub point: Point,
+    /// Distance from the start of the ray to `point`
+    pub dist: f64,
+    /// Index into `Map2D::lines` of the line that was hit, or `None` if a target was hit
+    pub line: Option<usize>,
+    /// Unit normal of the line that was hit facing the start of the ray,
+    /// or `None` if a target was hit
+    pub normal: Option<Point>,
+}
+
+/// How a ray cast with a maximum range ended, see `Map2D::raycast_outcome`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaycastOutcome {
+    /// The ray hit the map within the maximum range
+    Hit(RaycastHit),
+    /// The nearest hit is beyond the maximum range, so a sensor would get no return
+    BeyondRange(RaycastHit),
+    /// The ray left the map without hitting anything, e.g. from a pose outside of the map
+    LeftMap,
+}
+
+/// Identifies a labeled region of a map, see `Map2D::with_label`
+pub type RegionId = usize;
+
 /// A Simple 2D map of line segments
+///
+/// Raycasts only borrow the map and it has no interior mutability, so it is `Send + Sync`
+/// and can be shared between threads (e.g. in an `Arc` during parallel observation updates).
 #[derive(Debug)]
 pub struct Map2D {
     pub size: Point,
     pub targets: Vec<Pose3D>,
     pub vertices: Vec<Point>,
     pub lines: Vec<(usize, usize)>,
+    /// Indices into `lines` of the lines which reflect rays
+    pub mirrors: Vec<usize>,
+    /// Closed shapes (as indices into `vertices`) which are solid, see `Map2D::with_obstacles`
+    pub obstacles: Vec<Vec<usize>>,
+    /// The region each of `lines` belongs to, if it is labeled
+    pub labels: Vec<Option<RegionId>>,
+    /// Whether the map has no enclosing walls, so rays which don't hit anything
+    /// have an infinite range rather than none (see `Raycaster::raycast_distance`)
+    pub open_world: bool,
 }
 
 impl Map2D {
@@ -43,9 +95,44 @@ impl Map2D {
     where
         U: IntoIterator<Item = Object2D>,
     {
-        let mut vertices = Vec::new();
-        let mut lines = Vec::new();
-        let mut targets = Vec::new();
+        let mut map = Self {
+            size,
+            vertices: Vec::new(),
+            lines: Vec::new(),
+            targets: Vec::new(),
+            mirrors: Vec::new(),
+            obstacles: Vec::new(),
+            labels: Vec::new(),
+            open_world: false,
+        };
+        map.add_objects(objects, false);
+        map
+    }
+
+    /// Adds `objects` to the map, where the closed shapes (triangles and rectangles) are solid
+    /// obstacles (see `contains`). Other objects are added as they would be by `with_size`.
+    ///
+    /// The size of the map is unchanged, so obstacles should be inside of it.
+    pub fn with_obstacles<U>(mut self, objects: U) -> Self
+    where
+        U: IntoIterator<Item = Object2D>,
+    {
+        self.add_objects(objects, true);
+        self
+    }
+
+    fn add_objects<U>(&mut self, objects: U, solid: bool)
+    where
+        U: IntoIterator<Item = Object2D>,
+    {
+        let Self {
+            vertices,
+            lines,
+            targets,
+            mirrors,
+            obstacles,
+            ..
+        } = self;
         let mut add_vert = |point: Point| -> usize {
             if let Some(idx) = vertices.iter().position(|&v| v == point) {
                 idx
@@ -56,48 +143,87 @@ impl Map2D {
         };
 
         for object in objects {
-            match object {
-                Object2D::Line(p1, p2) => lines.push((add_vert(p1), add_vert(p2))),
-                Object2D::Triangle(c1, c2, c3) => {
-                    let v1 = add_vert(c1);
-                    let v2 = add_vert(c2);
-                    let v3 = add_vert(c3);
-                    lines.push((v1, v2));
-                    lines.push((v2, v3));
-                    lines.push((v3, v1));
+            let shape = match object {
+                Object2D::Line(p1, p2) => {
+                    lines.push((add_vert(p1), add_vert(p2)));
+                    continue;
+                }
+                Object2D::Mirror(p1, p2) => {
+                    mirrors.push(lines.len());
+                    lines.push((add_vert(p1), add_vert(p2)));
+                    continue;
                 }
+                Object2D::Target(p) => {
+                    targets.push(p);
+                    continue;
+                }
+                Object2D::Triangle(c1, c2, c3) => vec![add_vert(c1), add_vert(c2), add_vert(c3)],
                 Object2D::Rectangle(c1, c3) => {
                     let c2 = Point { x: c1.x, y: c3.y };
                     let c4 = Point { x: c3.x, y: c1.y };
-                    let v1 = add_vert(c1);
-                    let v2 = add_vert(c2);
-                    let v3 = add_vert(c3);
-                    let v4 = add_vert(c4);
-                    lines.push((v1, v2));
-                    lines.push((v2, v3));
-                    lines.push((v3, v4));
-                    lines.push((v4, v1));
+                    vec![add_vert(c1), add_vert(c2), add_vert(c3), add_vert(c4)]
                 }
                 Object2D::RectangleFour(c1, c2, c3, c4) => {
-                    let v1 = add_vert(c1);
-                    let v2 = add_vert(c2);
-                    let v3 = add_vert(c3);
-                    let v4 = add_vert(c4);
-                    lines.push((v1, v2));
-                    lines.push((v2, v3));
-                    lines.push((v3, v4));
-                    lines.push((v4, v1));
+                    vec![add_vert(c1), add_vert(c2), add_vert(c3), add_vert(c4)]
                 }
-                Object2D::Target(p) => targets.push(p),
             };
+            for (i, &v) in shape.iter().enumerate() {
+                lines.push((v, shape[(i + 1) % shape.len()]));
+            }
+            if solid {
+                obstacles.push(shape);
+            }
         }
+        self.labels.resize(self.lines.len(), None);
+    }
 
-        Self {
-            size,
-            vertices,
-            lines,
-            targets,
-        }
+    /// Labels the line at index `line` of `lines` as part of the region `label`
+    /// (e.g. a glass wall or a curtain), so observation models can treat beams hitting it differently
+    pub fn with_label(mut self, line: usize, label: RegionId) -> Self {
+        self.labels[line] = Some(label);
+        self
+    }
+
+    /// Makes rays which leave the map without hitting anything have an infinite range,
+    /// for outdoor maps without enclosing walls (see `open_world`)
+    pub fn with_open_world(mut self, open_world: bool) -> Self {
+        self.open_world = open_world;
+        self
+    }
+
+    /// The label of the line that was hit by a raycast, if any
+    pub fn hit_label(&self, hit: &RaycastHit) -> Option<RegionId> {
+        hit.line.and_then(|line| self.labels[line])
+    }
+
+    /// Generates a maze-like map of `rows` by `cols` square cells of size `cell_size`
+    /// surrounded by walls, where every cell except the `(row, col)` cells in `open` is solid.
+    /// Rows run along y and columns along x, starting from the origin.
+    pub fn grid_world(rows: usize, cols: usize, cell_size: f64, open: &[(usize, usize)]) -> Self {
+        let size = Point {
+            x: cols as f64 * cell_size,
+            y: rows as f64 * cell_size,
+        };
+        let corner = |row: usize, col: usize| Point {
+            x: col as f64 * cell_size,
+            y: row as f64 * cell_size,
+        };
+        let blocked = (0..rows)
+            .flat_map(|row| (0..cols).map(move |col| (row, col)))
+            .filter(|cell| !open.contains(cell))
+            .map(|(row, col)| Object2D::Rectangle(corner(row, col), corner(row + 1, col + 1)));
+        Self::with_size(size, vec![Object2D::Rectangle(Point::default(), size)])
+            .with_obstacles(blocked)
+    }
+
+    /// Generates a corridor `length` long along x and `width` wide along y,
+    /// closed at both ends, with a corner at the origin
+    pub fn corridor(length: f64, width: f64) -> Self {
+        let size = Point {
+            x: length,
+            y: width,
+        };
+        Self::with_size(size, vec![Object2D::Rectangle(Point::default(), size)])
     }
 
     /// Converts a file into a map. Returns `Ok` if file is formatted correctly.
@@ -180,33 +306,178 @@ impl Map2D {
         self.vertices[idx]
     }
 
+    /// Finds the distance along the ray from `start` to its intersection with the line
+    /// from `p1` to `p2`, if there is one.
+    ///
+    /// The ends of the line are extended by `RAYCAST_TIE_EPSILON` so that rays through
+    /// a vertex are not lost to floating point error.
+    fn ray_line_intersection(start: Pose, p1: Point, p2: Point) -> Option<f64> {
+        let ray = Point {
+            x: start.angle.cos(),
+            y: start.angle.sin(),
+        };
+        let v1 = start.position - p1;
+        let v2 = p2 - p1;
+        let v3 = Point {
+            x: -ray.y,
+            y: ray.x,
+        };
+        let div = v2.dot(v3);
+        if div == 0. {
+            return None;
+        }
+        let t1 = v2.cross_mag(v1) / div;
+        let t2 = v1.dot(v3) / div;
+        if t1 >= 0. && t2 >= -RAYCAST_TIE_EPSILON && t2 <= 1. + RAYCAST_TIE_EPSILON {
+            Some(t1)
+        } else {
+            None
+        }
+    }
+
     pub fn raycast(&self, start: Pose) -> Option<Point> {
+        self.raycast_hit(start).map(|hit| hit.point)
+    }
+
+    /// Raycasts from a sensor which moves from `from` to `to` over the course of a scan,
+    /// using the pose `fraction` of the way through the motion (see `Pose::lerp`).
+    ///
+    /// Each beam of a scan can use the fraction of the scan at which it was measured
+    /// to model motion distortion.
+    pub fn raycast_swept(&self, from: Pose, to: Pose, fraction: f64) -> Option<Point> {
+        self.raycast(from.lerp(to, fraction))
+    }
+
+    /// Raycasts a beam with an angular width of `beam_width` centered on the angle of `start`,
+    /// like a real lidar beam which returns the nearest surface within a small cone.
+    ///
+    /// `sub_rays` rays are cast evenly across the beam and the minimum hit distance is returned
+    /// as a point along the center of the beam. With fewer than two sub rays this is `raycast`.
+    pub fn raycast_beam(&self, start: Pose, beam_width: f64, sub_rays: usize) -> Option<Point> {
+        if sub_rays < 2 {
+            return self.raycast(start);
+        }
+        (0..sub_rays)
+            .filter_map(|i| {
+                let offset = beam_width * (i as f64 / (sub_rays - 1) as f64 - 0.5);
+                self.raycast_hit(start.with_angle(start.angle + offset))
+            })
+            .map(|hit| hit.dist)
+            .fold(None, |min: Option<f64>, dist| {
+                Some(min.map_or(dist, |min| min.min(dist)))
+            })
+            .map(|dist| start.position + Point::polar(start.angle, dist))
+    }
+
+    /// Raycasts from `samples` origins spread evenly across ±`jitter` perpendicular to the ray
+    /// and returns the mean hit distance as a point along the ray from `start`.
+    /// Rays which miss are skipped. With fewer than two samples this is `raycast`.
+    ///
+    /// Near thin features a small change in pose can flip a single ray between the feature
+    /// and the wall behind it, while the mean changes by only a fraction of that,
+    /// which smooths out the error of particles near the feature.
+    pub fn raycast_jittered(&self, start: Pose, jitter: f64, samples: usize) -> Option<Point> {
+        if samples < 2 {
+            return self.raycast(start);
+        }
+        let perpendicular = Point::polar(start.angle + PI / 2., 1.);
+        let (total, hits) = (0..samples)
+            .filter_map(|i| {
+                let offset = jitter * (2. * i as f64 / (samples - 1) as f64 - 1.);
+                self.raycast_hit(Pose {
+                    position: start.position + perpendicular * offset,
+                    ..start
+                })
+            })
+            .fold((0., 0), |(total, hits), hit| (total + hit.dist, hits + 1));
+        if hits == 0 {
+            return None;
+        }
+        Some(start.position + Point::polar(start.angle, total / hits as f64))
+    }
+
+    /// Raycasts from `origin` at each of `bearings` (relative to the angle of `origin`),
+    /// returning the world coordinates of each hit within `max_range`.
+    /// Rays which miss are skipped.
+    pub fn raycast_cloud(
+        &self,
+        origin: Pose,
+        bearings: &[f64],
+        max_range: Option<f64>,
+    ) -> Vec<Point> {
+        let max_range = max_range.unwrap_or(INFINITY);
+        bearings
+            .iter()
+            .filter_map(|&bearing| self.raycast_hit(origin.with_angle(origin.angle + bearing)))
+            .filter(|hit| hit.dist <= max_range)
+            .map(|hit| hit.point)
+            .collect()
+    }
+
+    /// Raycasts from `origin` at each of `bearings` (relative to the angle of `origin`),
+    /// returning the range along each beam, or `None` for beams which miss
+    pub fn raycast_polar(&self, origin: Pose, bearings: &[f64]) -> Vec<Option<f64>> {
+        bearings
+            .iter()
+            .map(|&bearing| {
+                self.raycast_hit(origin.with_angle(origin.angle + bearing))
+                    .map(|hit| hit.dist)
+            })
+            .collect()
+    }
+
+    /// Raycasts from `start` like `raycast_hit`, but tells apart a hit beyond `max_range`
+    /// (a legitimate no-return) from a ray which leaves the map without hitting anything
+    /// (which a closed map only allows from poses outside of it)
+    pub fn raycast_outcome(&self, start: Pose, max_range: f64) -> RaycastOutcome {
+        match self.raycast_hit(start) {
+            Some(hit) if hit.dist <= max_range => RaycastOutcome::Hit(hit),
+            Some(hit) => RaycastOutcome::BeyondRange(hit),
+            None => RaycastOutcome::LeftMap,
+        }
+    }
+
+    /// Finds the closest intersection of the ray from `start` with the map,
+    /// along with the index of the line that was hit (`None` if a target was hit).
+    ///
+    /// Hits within `RAYCAST_TIE_EPSILON` of each other (e.g. a ray through a vertex shared
+    /// by two lines) are considered the same distance, and the lowest line index wins,
+    /// so that results do not depend on floating point error.
+    pub fn raycast_hit(&self, start: Pose) -> Option<RaycastHit> {
+        self.raycast_hit_skipping(start, None)
+    }
+
+    /// `raycast_hit` ignoring the line at index `skip_line`
+    /// (e.g. the line that a reflected ray starts on)
+    fn raycast_hit_skipping(&self, start: Pose, skip_line: Option<usize>) -> Option<RaycastHit> {
         let ray = Point {
             x: start.angle.cos(),
             y: start.angle.sin(),
         };
-        let mut closest_intersection: Option<Point> = None;
-        let mut closest_intersection_dist = 0.;
-        for line in &self.lines {
-            let v1 = start.position - self.get_vertex(line.0);
-            let v2 = self.get_vertex(line.1) - self.get_vertex(line.0);
-            let v3 = Point {
-                x: -ray.y,
-                y: ray.x,
-            };
-            let div = v2.dot(v3);
-            if div == 0. {
+        let mut closest: Option<RaycastHit> = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            if skip_line == Some(i) {
                 continue;
             }
-            let t1 = v2.cross_mag(v1) / div;
-            let t2 = v1.dot(v3) / div;
-            if t1 >= 0. && t2 >= 0. && t2 <= 1. {
-                let intersection = start.position + ray * t1;
-                // let intersection_check = self.get_vertex(line.0) + (self.get_vertex(line.1) - self.get_vertex(line.0)) * t2;
-                let dist = intersection.dist(start.position);
-                if closest_intersection == None || closest_intersection_dist > dist {
-                    closest_intersection = Some(intersection);
-                    closest_intersection_dist = dist;
+            let (p1, p2) = (self.get_vertex(line.0), self.get_vertex(line.1));
+            if let Some(dist) = Self::ray_line_intersection(start, p1, p2) {
+                if closest.map_or(true, |hit| hit.dist - dist > RAYCAST_TIE_EPSILON) {
+                    let direction = p2 - p1;
+                    let normal = Point {
+                        x: -direction.y,
+                        y: direction.x,
+                    }
+                    .normalize();
+                    closest = Some(RaycastHit {
+                        point: start.position + ray * dist,
+                        dist,
+                        line: Some(i),
+                        normal: Some(if normal.dot(ray) > 0. {
+                            normal * -1.
+                        } else {
+                            normal
+                        }),
+                    });
                 }
             }
         }
@@ -215,60 +486,126 @@ impl Map2D {
             let point2d = target.position.clone().without_z();
             if (start.position.angle_to(point2d) - start.angle).abs() < 0.01 {
                 let dist = point2d.dist(start.position);
-                if closest_intersection == None || closest_intersection_dist > dist {
-                    closest_intersection = Some(point2d);
-                    closest_intersection_dist = dist;
+                if closest.map_or(true, |hit| hit.dist - dist > RAYCAST_TIE_EPSILON) {
+                    closest = Some(RaycastHit {
+                        point: point2d,
+                        dist,
+                        line: None,
+                        normal: None,
+                    });
                 }
             }
         }
-        closest_intersection
+        closest
     }
 
-    pub fn raycast_with_maps(start: Pose, maps: Vec<Arc<Map2D>>) -> Option<Point> {
-        let ray = Point {
-            x: start.angle.cos(),
-            y: start.angle.sin(),
-        };
-        let mut closest_intersection: Option<Point> = None;
-        let mut closest_intersection_dist = 0.;
-        
-        for map in maps {
-            for line in &map.lines {
-                let v1 = start.position - map.get_vertex(line.0);
-                let v2 = map.get_vertex(line.1) - map.get_vertex(line.0);
-                let v3 = Point {
-                    x: -ray.y,
-                    y: ray.x,
-                };
-                let div = v2.dot(v3);
-                if div == 0. {
-                    continue;
+    /// Raycasts from `pose`, reflecting off of mirrors (see `Object2D::Mirror`)
+    /// up to `max_bounces` times.
+    ///
+    /// Returns the final hit and the total length of the path to it,
+    /// or `None` if the ray or any of its reflections misses.
+    /// If the ray is still hitting mirrors after `max_bounces` reflections,
+    /// the last mirror hit is treated as a regular line.
+    pub fn raycast_reflective(&self, pose: Pose, max_bounces: usize) -> Option<(Point, f64)> {
+        let mut start = pose;
+        let mut skip_line = None;
+        let mut path_length = 0.;
+        let mut bounces = 0;
+        loop {
+            let hit = self.raycast_hit_skipping(start, skip_line)?;
+            path_length += hit.dist;
+            match (hit.line, hit.normal) {
+                (Some(line), Some(normal))
+                    if bounces < max_bounces && self.mirrors.contains(&line) =>
+                {
+                    let ray = Point::polar(start.angle, 1.);
+                    let reflected = ray - normal * (2. * ray.dot(normal));
+                    start = Pose {
+                        angle: reflected.y.atan2(reflected.x),
+                        position: hit.point,
+                    };
+                    skip_line = Some(line);
+                    bounces += 1;
                 }
-                let t1 = v2.cross_mag(v1) / div;
-                let t2 = v1.dot(v3) / div;
-                if t1 >= 0. && t2 >= 0. && t2 <= 1. {
-                    let intersection = start.position + ray * t1;
-                    // let intersection_check = self.get_vertex(line.0) + (self.get_vertex(line.1) - self.get_vertex(line.0)) * t2;
-                    let dist = intersection.dist(start.position);
-                    if closest_intersection == None || closest_intersection_dist > dist {
-                        closest_intersection = Some(intersection);
-                        closest_intersection_dist = dist;
-                    }
+                _ => return Some((hit.point, path_length)),
+            }
+        }
+    }
+
+    /// Finds the line closest to `point`, returning its index into `lines`,
+    /// the closest point on it and the distance to that point.
+    ///
+    /// Returns `None` if the map has no lines.
+    ///
+    /// Scans every line (the map has no spatial index).
+    pub fn nearest_segment(&self, point: Point) -> Option<(usize, Point, f64)> {
+        let mut nearest: Option<(usize, Point, f64)> = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            let closest =
+                point.closest_on_segment(self.get_vertex(line.0), self.get_vertex(line.1));
+            let dist = closest.dist(point);
+            if nearest.map_or(true, |(_, _, nearest_dist)| dist < nearest_dist) {
+                nearest = Some((i, closest, dist));
+            }
+        }
+        nearest
+    }
+
+    /// Finds the shortest distance between the line segments from `a1` to `a2` and from `b1` to `b2`
+    fn segment_distance(a1: Point, a2: Point, b1: Point, b2: Point) -> f64 {
+        let side = |p: Point, start: Point, end: Point| (end - start).cross_mag(p - start);
+        let (d1, d2) = (side(b1, a1, a2), side(b2, a1, a2));
+        let (d3, d4) = (side(a1, b1, b2), side(a2, b1, b2));
+        if d1 * d2 < 0. && d3 * d4 < 0. {
+            return 0.;
+        }
+        b1.closest_on_segment(a1, a2)
+            .dist(b1)
+            .min(b2.closest_on_segment(a1, a2).dist(b2))
+            .min(a1.closest_on_segment(b1, b2).dist(a1))
+            .min(a2.closest_on_segment(b1, b2).dist(a2))
+    }
+
+    /// Whether a robot of radius `radius` can move in a straight line from `from` to `to`
+    /// without touching any line of the map
+    pub fn path_clear(&self, from: Point, to: Point, radius: f64) -> bool {
+        self.lines.iter().all(|line| {
+            Self::segment_distance(from, to, self.get_vertex(line.0), self.get_vertex(line.1))
+                > radius
+        })
+    }
+
+    /// Whether `point` is inside one of the map's obstacles (see `obstacles`).
+    /// Lines which aren't part of a closed shape never contain a point.
+    pub fn contains(&self, point: Point) -> bool {
+        self.obstacles.iter().any(|shape| {
+            // Even-odd rule with a ray cast towards +x
+            let mut inside = false;
+            for (i, &v1) in shape.iter().enumerate() {
+                let p1 = self.get_vertex(v1);
+                let p2 = self.get_vertex(shape[(i + 1) % shape.len()]);
+                if (p1.y > point.y) != (p2.y > point.y)
+                    && point.x < p1.x + (point.y - p1.y) / (p2.y - p1.y) * (p2.x - p1.x)
+                {
+                    inside = !inside;
                 }
             }
-            for target in &map.targets {
-                // TODO: tune? fuzzy comparison for slope comparison
-                let point2d = target.position.clone().without_z();
-                if (start.position.angle_to(point2d) - start.angle).abs() < 0.01 {
-                    let dist = point2d.dist(start.position);
-                    if closest_intersection == None || closest_intersection_dist > dist {
-                        closest_intersection = Some(point2d);
-                        closest_intersection_dist = dist;
-                    }
+            inside
+        })
+    }
+
+    pub fn raycast_with_maps(start: Pose, maps: Vec<Arc<Map2D>>) -> Option<Point> {
+        let mut closest: Option<RaycastHit> = None;
+        for map in maps {
+            if let Some(hit) = map.raycast_hit(start) {
+                if closest.map_or(true, |closest| {
+                    closest.dist - hit.dist > RAYCAST_TIE_EPSILON
+                }) {
+                    closest = Some(hit);
                 }
             }
         }
-        closest_intersection
+        closest.map(|hit| hit.point)
     }
 
     // TODO: name this wtf
@@ -296,3 +633,271 @@ impl Map2D {
         sensed_objects
     }
 }
+
+/// Anything that can find the distance along a ray to the nearest obstacle,
+/// so that sensor models, dummy sensors and localizers can be shared between map representations
+/// (e.g. `Map2D` and `MapSdf`)
+pub trait Raycaster {
+    /// Finds the distance from `start` to the nearest obstacle in the direction of `start.angle`,
+    /// or `None` if the ray does not hit anything
+    fn raycast_distance(&self, start: Pose) -> Option<f64>;
+
+    /// Like `raycast_distance`, along with the region of the obstacle that was hit if it is labeled
+    /// (see `Map2D::with_label`). Maps without labels never have a region.
+    fn raycast_distance_labeled(&self, start: Pose) -> (Option<f64>, Option<RegionId>) {
+        (self.raycast_distance(start), None)
+    }
+
+    /// The far corner of the map, which spans from the origin to it
+    fn size(&self) -> Point;
+
+    /// Whether `point` is inside of an obstacle
+    fn contains(&self, point: Point) -> bool;
+
+    /// Samples a point uniformly from the free space of the map (between the origin and `size`)
+    /// by rejection sampling against `contains`.
+    ///
+    /// Returns `None` after `MAX_FREE_POINT_ATTEMPTS` rejections
+    /// so that maps without any free space don't hang.
+    fn sample_free_point<R: Rng>(&self, rng: &mut R) -> Option<Point>
+    where
+        Self: Sized,
+    {
+        let size = self.size();
+        (0..MAX_FREE_POINT_ATTEMPTS)
+            .map(|_| Point {
+                x: rng.gen::<f64>() * size.x,
+                y: rng.gen::<f64>() * size.y,
+            })
+            .find(|&point| !self.contains(point))
+    }
+}
+
+impl Raycaster for Map2D {
+    /// Rays which don't hit anything have a distance of `f64::INFINITY` on an open world map
+    fn raycast_distance(&self, start: Pose) -> Option<f64> {
+        self.raycast_distance_labeled(start).0
+    }
+
+    fn raycast_distance_labeled(&self, start: Pose) -> (Option<f64>, Option<RegionId>) {
+        match self.raycast_hit(start) {
+            Some(hit) => (Some(hit.dist), self.hit_label(&hit)),
+            None if self.open_world => (Some(f64::INFINITY), None),
+            None => (None, None),
+        }
+    }
+
+    fn size(&self) -> Point {
+        self.size
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        Map2D::contains(self, point)
+    }
+}
+
+/// A map whose obstacles are described by a signed distance function (`sdf`),
+/// which gives the distance from a point to the nearest obstacle (negative inside obstacles).
+///
+/// Raycasts are done by raymarching (sphere tracing) rather than intersecting segments,
+/// which allows smooth, implicit obstacles.
+///
+/// `size` bounds the map: rays which leave it are considered to miss.
+///
+/// `max_steps` and `epsilon` are the maximum number of steps and the hit distance used by `raycast_distance`
+pub struct MapSdf<F>
+where
+    F: Fn(Point) -> f64,
+{
+    pub size: Point,
+    pub max_steps: usize,
+    pub epsilon: f64,
+    sdf: F,
+}
+
+impl<F> MapSdf<F>
+where
+    F: Fn(Point) -> f64,
+{
+    pub fn new(size: Point, sdf: F) -> Self {
+        Self {
+            size,
+            max_steps: 100,
+            epsilon: 1e-6,
+            sdf,
+        }
+    }
+
+    pub fn with_raymarch_params(mut self, max_steps: usize, epsilon: f64) -> Self {
+        self.max_steps = max_steps;
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// The signed distance from `point` to the nearest obstacle
+    pub fn distance(&self, point: Point) -> f64 {
+        (self.sdf)(point)
+    }
+
+    /// Marches along the ray from `pose` by the distance to the nearest obstacle at each step
+    /// until within `epsilon` of an obstacle, returning the distance travelled.
+    ///
+    /// Returns `None` if the ray leaves the map or does not converge within `max_steps`
+    pub fn raymarch(&self, pose: Pose, max_steps: usize, epsilon: f64) -> Option<f64> {
+        let direction = Point::polar(pose.angle, 1.);
+        let mut dist = 0.;
+        for _ in 0..max_steps {
+            let point = pose.position + direction * dist;
+            if point.x < 0. || point.y < 0. || point.x > self.size.x || point.y > self.size.y {
+                return None;
+            }
+            let step = self.distance(point);
+            if step < epsilon {
+                return Some(dist);
+            }
+            dist += step;
+        }
+        None
+    }
+}
+
+impl<F> Raycaster for MapSdf<F>
+where
+    F: Fn(Point) -> f64,
+{
+    fn raycast_distance(&self, start: Pose) -> Option<f64> {
+        self.raymarch(start, self.max_steps, self.epsilon)
+    }
+
+    fn size(&self) -> Point {
+        self.size
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        self.distance(point) < 0.
+    }
+}
+
+/// A compact version of a `Map2D` that stores its geometry as `f32`s for memory-constrained platforms
+///
+/// Targets are not stored, so raycasts only intersect lines
+#[cfg(feature = "f32")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map2DF32 {
+    pub size: [f32; 2],
+    pub vertices: Vec<[f32; 2]>,
+    pub lines: Vec<(u32, u32)>,
+}
+
+#[cfg(feature = "f32")]
+impl Map2DF32 {
+    /// Same as `Map2D::new`, without building a `Map2D` first
+    pub fn new<U>(objects: U) -> Self
+    where
+        U: IntoIterator<Item = Object2D>,
+    {
+        let mut map = Self::with_size(Point::default(), objects);
+        for vertex in &map.vertices {
+            map.size[0] = map.size[0].max(vertex[0]);
+            map.size[1] = map.size[1].max(vertex[1]);
+        }
+        map
+    }
+
+    /// Same as `Map2D::with_size`, without building a `Map2D` first.
+    /// Lines are stored in the same order as in a `Map2D` of the same objects
+    pub fn with_size<U>(size: Point, objects: U) -> Self
+    where
+        U: IntoIterator<Item = Object2D>,
+    {
+        let mut vertices: Vec<[f32; 2]> = Vec::new();
+        let mut lines = Vec::new();
+        for object in objects {
+            let corners = match object {
+                Object2D::Line(p1, p2) | Object2D::Mirror(p1, p2) => vec![p1, p2],
+                Object2D::Triangle(c1, c2, c3) => vec![c1, c2, c3, c1],
+                Object2D::Rectangle(c1, c3) => vec![
+                    c1,
+                    Point { x: c1.x, y: c3.y },
+                    c3,
+                    Point { x: c3.x, y: c1.y },
+                    c1,
+                ],
+                Object2D::RectangleFour(c1, c2, c3, c4) => vec![c1, c2, c3, c4, c1],
+                Object2D::Target(_) => vec![],
+            };
+            let indices: Vec<u32> = corners
+                .iter()
+                .map(|corner| {
+                    let vertex = [corner.x as f32, corner.y as f32];
+                    match vertices.iter().position(|&v| v == vertex) {
+                        Some(idx) => idx as u32,
+                        None => {
+                            vertices.push(vertex);
+                            vertices.len() as u32 - 1
+                        }
+                    }
+                })
+                .collect();
+            lines.extend(indices.windows(2).map(|pair| (pair[0], pair[1])));
+        }
+        Self {
+            size: [size.x as f32, size.y as f32],
+            vertices,
+            lines,
+        }
+    }
+
+    /// Same as `Map2D::raycast`, but all of the intersection math is done in `f32`.
+    ///
+    /// Like `Map2D::raycast_hit`, the ends of lines are extended by `RAYCAST_TIE_EPSILON_F32`
+    /// and ties go to the line with the lowest index.
+    pub fn raycast(&self, start: Pose) -> Option<Point> {
+        let origin = [start.position.x as f32, start.position.y as f32];
+        let ray = [(start.angle as f32).cos(), (start.angle as f32).sin()];
+        let normal = [-ray[1], ray[0]];
+        let mut closest_dist: Option<f32> = None;
+        for &(v1, v2) in &self.lines {
+            let p1 = self.vertices[v1 as usize];
+            let p2 = self.vertices[v2 as usize];
+            let to_origin = [origin[0] - p1[0], origin[1] - p1[1]];
+            let line = [p2[0] - p1[0], p2[1] - p1[1]];
+            let div = line[0] * normal[0] + line[1] * normal[1];
+            if div == 0. {
+                continue;
+            }
+            let t1 = (line[0] * to_origin[1] - line[1] * to_origin[0]) / div;
+            let t2 = (to_origin[0] * normal[0] + to_origin[1] * normal[1]) / div;
+            if t1 >= 0.
+                && t2 >= -RAYCAST_TIE_EPSILON_F32
+                && t2 <= 1. + RAYCAST_TIE_EPSILON_F32
+                && closest_dist.map_or(true, |d| d - t1 > RAYCAST_TIE_EPSILON_F32)
+            {
+                closest_dist = Some(t1);
+            }
+        }
+        closest_dist.map(|dist| Point {
+            x: (origin[0] + ray[0] * dist) as f64,
+            y: (origin[1] + ray[1] * dist) as f64,
+        })
+    }
+}
+
+#[cfg(feature = "f32")]
+impl From<&Map2D> for Map2DF32 {
+    fn from(map: &Map2D) -> Self {
+        Self {
+            size: [map.size.x as f32, map.size.y as f32],
+            vertices: map
+                .vertices
+                .iter()
+                .map(|v| [v.x as f32, v.y as f32])
+                .collect(),
+            lines: map
+                .lines
+                .iter()
+                .map(|&(v1, v2)| (v1 as u32, v2 as u32))
+                .collect(),
+        }
+    }
+}
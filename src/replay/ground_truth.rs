@@ -0,0 +1,103 @@
+use crate::utility::Pose;
+use std::time::Instant;
+
+/// A source of the true pose of the robot, used to evaluate localizers
+pub trait GroundTruth {
+    /// Gets the true pose of the robot at time `t`
+    fn pose_at(&self, t: Instant) -> Pose;
+}
+
+/// A pose recorded `time` seconds after the start of a trajectory
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct TimestampedPose {
+    pub time: f64,
+    pub pose: Pose,
+}
+
+/// Ground truth from a recorded trajectory.
+///
+/// Poses between two samples are linearly interpolated.
+/// Poses before the first sample or after the last sample are clamped to that sample.
+pub struct RecordedGroundTruth {
+    pub start: Instant,
+    samples: Vec<TimestampedPose>,
+}
+
+impl RecordedGroundTruth {
+    /// Creates a new recorded ground truth from `samples` whose times are relative to `start`
+    pub fn new(start: Instant, mut samples: Vec<TimestampedPose>) -> Self {
+        samples.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { start, samples }
+    }
+
+    /// Converts a file into a trajectory. Returns `Ok` if file is formatted correctly.
+    /// The file should be formatted as a linebreak delimitered list of samples
+    /// in the format t angle x y, where t is the number of seconds after `start`. All values are f64s. For example,
+    /// ```text
+    /// t angle x y
+    /// t angle x y
+    /// ...
+    /// ```
+    pub fn from_file(path: &str, start: Instant) -> std::io::Result<Self> {
+        use std::fs::File;
+        use std::io::{Error, ErrorKind, Read};
+        let mut file = File::open(path)?;
+        let mut buf = String::new();
+        file.read_to_string(&mut buf)?;
+        let mut samples = Vec::new();
+        for line_text in buf.lines().filter(|l| !l.trim().is_empty()) {
+            let vals = line_text
+                .split_whitespace()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+            if vals.len() < 4 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Incorrect trajectory: sample with less than four values provided",
+                ));
+            }
+            if !vals[0].is_finite() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Incorrect trajectory: sample with a time which is not finite",
+                ));
+            }
+            samples.push(TimestampedPose {
+                time: vals[0],
+                pose: Pose {
+                    angle: vals[1],
+                    position: (vals[2], vals[3]).into(),
+                },
+            });
+        }
+        if samples.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Incorrect trajectory: no samples",
+            ));
+        }
+        Ok(Self::new(start, samples))
+    }
+
+    pub fn samples(&self) -> &[TimestampedPose] {
+        &self.samples
+    }
+}
+
+impl GroundTruth for RecordedGroundTruth {
+    fn pose_at(&self, t: Instant) -> Pose {
+        let time = t.saturating_duration_since(self.start).as_secs_f64();
+        match self.samples.iter().position(|sample| sample.time > time) {
+            None => self.samples.last().map(|s| s.pose).unwrap_or_default(),
+            Some(0) => self.samples[0].pose,
+            Some(i) => {
+                let (before, after) = (self.samples[i - 1], self.samples[i]);
+                before.pose.lerp(
+                    after.pose,
+                    (time - before.time) / (after.time - before.time),
+                )
+            }
+        }
+    }
+}
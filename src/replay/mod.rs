@@ -1,3 +1,4 @@
+pub mod ground_truth;
 pub mod log;
 #[cfg(feature = "render")]
 pub mod render;
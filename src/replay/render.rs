@@ -70,6 +70,151 @@ pub fn point_cloud<G>(
     }
 }
 
+/// Draws a row-major `bins_x` by `bins_y` histogram over a map of size `size`
+/// with the opacity of each bin proportional to its mass
+pub fn draw_heatmap<G>(
+    histogram: &[f64],
+    bins_x: usize,
+    bins_y: usize,
+    size: Point,
+    color: [f32; 4],
+    scale: f64,
+    offset: Point,
+    transform: [[f64; 3]; 2],
+    g: &mut G,
+) where
+    G: Graphics,
+{
+    let max_mass = histogram.iter().cloned().fold(0., f64::max);
+    if max_mass <= 0. {
+        return;
+    }
+    let bin_size = Point {
+        x: size.x / bins_x as f64,
+        y: size.y / bins_y as f64,
+    } * scale;
+    for (i, &mass) in histogram.iter().enumerate().filter(|(_, &m)| m > 0.) {
+        let corner = offset
+            + Point {
+                x: (i % bins_x) as f64 * bin_size.x,
+                y: (i / bins_x) as f64 * bin_size.y,
+            };
+        rectangle(
+            [
+                color[0],
+                color[1],
+                color[2],
+                color[3] * (mass / max_mass) as f32,
+            ],
+            [corner.x, corner.y, bin_size.x, bin_size.y],
+            transform,
+            g,
+        );
+    }
+}
+
+/// Finds where the true pose and predicted pose are drawn by `draw_error_overlay`
+pub fn error_overlay_endpoints(
+    true_pose: Pose,
+    predicted: Pose,
+    scale: f64,
+    offset: Point,
+) -> (Point, Point) {
+    (
+        offset + true_pose.position * scale,
+        offset + predicted.position * scale,
+    )
+}
+
+/// Draws the true pose and the predicted pose of the robot
+/// connected by a line labeled with the distance between them
+pub fn draw_error_overlay<G, C>(
+    true_pose: Pose,
+    predicted: Pose,
+    true_color: [f32; 4],
+    predicted_color: [f32; 4],
+    error_color: [f32; 4],
+    line_radius: f64,
+    scale: f64,
+    offset: Point,
+    glyphs: &mut C,
+    transform: [[f64; 3]; 2],
+    g: &mut G,
+) where
+    G: Graphics<Texture = C::Texture>,
+    C: character::CharacterCache,
+{
+    let (true_point, predicted_point) =
+        error_overlay_endpoints(true_pose, predicted, scale, offset);
+    line_from_to(
+        error_color,
+        line_radius,
+        true_point,
+        predicted_point,
+        transform,
+        g,
+    );
+    isoceles_triangle(true_color, offset, scale, 1., true_pose, transform, g);
+    isoceles_triangle(predicted_color, offset, scale, 1., predicted, transform, g);
+    let label_position = (true_point + predicted_point) * 0.5;
+    text::Text::new_color(error_color, 12)
+        .draw(
+            &format!("{:.3}", true_pose.position.dist(predicted.position)),
+            glyphs,
+            &DrawState::default(),
+            transform.trans(label_position.x, label_position.y),
+            g,
+        )
+        .ok();
+}
+
+/// Finds where the rays of a scan from `origin` are drawn by `draw_scan_comparison`,
+/// as the start and end of the ray for each of `ranges` at the matching bearing in `bearings`
+/// (relative to the angle of `origin`). Beams without a range are skipped.
+pub fn scan_ray_endpoints(
+    origin: Pose,
+    ranges: &[Option<f64>],
+    bearings: &[f64],
+    scale: f64,
+    offset: Point,
+) -> Vec<(Point, Point)> {
+    let start = offset + origin.position * scale;
+    ranges
+        .iter()
+        .zip(bearings)
+        .filter_map(|(range, &bearing)| {
+            range.map(|range| {
+                let end = origin.position + Point::polar(origin.angle + bearing, range);
+                (start, offset + end * scale)
+            })
+        })
+        .collect()
+}
+
+/// Draws the `expected` scan (e.g. raycast from the mean of the belief)
+/// and the `actual` scan as rays from `origin` in different colors
+pub fn draw_scan_comparison<G>(
+    origin: Pose,
+    expected: &[Option<f64>],
+    actual: &[Option<f64>],
+    bearings: &[f64],
+    expected_color: [f32; 4],
+    actual_color: [f32; 4],
+    line_radius: f64,
+    scale: f64,
+    offset: Point,
+    transform: [[f64; 3]; 2],
+    g: &mut G,
+) where
+    G: Graphics,
+{
+    for (ranges, color) in [(expected, expected_color), (actual, actual_color)].iter() {
+        for (start, end) in scan_ray_endpoints(origin, ranges, bearings, scale, offset) {
+            line_from_to(*color, line_radius, start, end, transform, g);
+        }
+    }
+}
+
 pub fn isoceles_triangle<G: Graphics>(
     color: [f32; 4],
     margin: Point,
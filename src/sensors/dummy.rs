@@ -1,5 +1,5 @@
 use crate::{
-    map::Map2D,
+    map::{Map2D, Raycaster},
     sensors::{LimitedSensor, Sensor, SensorSink},
     utility::{Point, Point3D, Pose, Pose3D},
 };
@@ -254,12 +254,14 @@ impl Sensor for DummyDistanceSensor {
 
 impl LimitedSensor<f64> for DummyDistanceSensor {}
 
-pub struct DummyLidar {
-    pub map: Arc<Map2D>,
+/// A lidar which scans a map (`Map2D` by default, or anything else which can be raycast)
+pub struct DummyLidar<M = Map2D> {
+    pub map: Arc<M>,
     pub robot_pose: Pose,
     pub range: Option<Range<f64>>,
     dist_noise: Normal<f64>,
     angle_noise: Normal<f64>,
+    bias_noise: Normal<f64>,
     resolution: usize,
     period: Duration,
     relative_pose: Pose,
@@ -267,7 +269,7 @@ pub struct DummyLidar {
     scan_timestamp: Instant,
 }
 
-impl DummyLidar {
+impl<M: Raycaster> DummyLidar<M> {
     /// Creates a new dummy lidar
     /// `map` is the map it scans on
     /// `robot_pose` is the starting pose of the robot
@@ -278,7 +280,7 @@ impl DummyLidar {
     /// `relative_pose` is the pose of the lidar relative to the robot
     /// `range` this is the range of the lidar, defaults to infinite
     pub fn new(
-        map: Arc<Map2D>,
+        map: Arc<M>,
         robot_pose: Pose,
         dist_noise: Normal<f64>,
         angle_noise: Normal<f64>,
@@ -292,6 +294,7 @@ impl DummyLidar {
             robot_pose,
             dist_noise,
             angle_noise,
+            bias_noise: Normal::new(0., 0.).unwrap(),
             resolution,
             period,
             relative_pose,
@@ -301,13 +304,25 @@ impl DummyLidar {
         }
     }
 
+    /// Sets the noise that's added to the distance of every point of a scan,
+    /// sampled once per scan (e.g. the bias of the sensor for that scan),
+    /// so that the noise of the points of a scan is correlated. Defaults to none.
+    pub fn with_bias_noise(mut self, bias_noise: Normal<f64>) -> Self {
+        self.bias_noise = bias_noise;
+        self
+    }
+
     pub fn update_pose(&mut self, new_pose: Pose) {
         self.robot_pose = new_pose;
     }
+}
+
+impl DummyLidar<Map2D> {
     pub fn update_with_maps(&mut self, maps: Vec<Arc<Map2D>>) {
         let mut rng = thread_rng();
         let mut scan = vec![];
         let increment = 2. * PI / self.resolution as f64;
+        let bias = self.bias_noise.sample(&mut rng);
         let mut all_maps = maps;
         all_maps.push(self.map.clone());
         for i in 0..self.resolution {
@@ -330,7 +345,7 @@ impl DummyLidar {
                     scan.push(Point::polar(
                         scan_point.angle_to(self.robot_pose.position) - self.robot_pose.angle
                             + self.angle_noise.sample(&mut rng),
-                        lidar_dist + lidar_dist.powi(2) * self.dist_noise.sample(&mut rng),
+                        lidar_dist + bias + lidar_dist.powi(2) * self.dist_noise.sample(&mut rng),
                     ))
                 }
                 _ => (),
@@ -339,7 +354,7 @@ impl DummyLidar {
         self.scan = scan;
     }
 }
-impl Sensor for DummyLidar {
+impl<M: Raycaster> Sensor for DummyLidar<M> {
     type Output = Vec<Point>;
 
     fn update(&mut self) {
@@ -353,26 +368,26 @@ impl Sensor for DummyLidar {
         let mut rng = thread_rng();
         let mut scan = vec![];
         let increment = 2. * PI / self.resolution as f64;
+        let bias = self.bias_noise.sample(&mut rng);
         for i in 0..self.resolution {
-            match self.map.raycast(
+            match self.map.raycast_distance(
                 self.robot_pose
                     + Pose {
                         angle: increment * i as f64,
                         ..Pose::default()
                     },
             ) {
-                Some(scan_point)
+                Some(lidar_dist)
                     if self
                         .range
                         .clone()
                         .unwrap_or(0.0..INFINITY)
-                        .contains(&scan_point.dist(self.robot_pose.position)) =>
+                        .contains(&lidar_dist) =>
                 {
-                    let lidar_dist = scan_point.dist(self.robot_pose.position);
+                    // the angle from the hit back to the lidar, as in `update_with_maps`
                     scan.push(Point::polar(
-                        scan_point.angle_to(self.robot_pose.position) - self.robot_pose.angle
-                            + self.angle_noise.sample(&mut rng),
-                        lidar_dist + lidar_dist.powi(2) * self.dist_noise.sample(&mut rng),
+                        PI + increment * i as f64 + self.angle_noise.sample(&mut rng),
+                        lidar_dist + bias + lidar_dist.powi(2) * self.dist_noise.sample(&mut rng),
                     ))
                 }
                 _ => (),
@@ -388,9 +403,13 @@ impl Sensor for DummyLidar {
     fn relative_pose(&self) -> Pose {
         self.relative_pose
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        Some(self.period)
+    }
 }
 
-impl LimitedSensor<Range<f64>> for DummyLidar {
+impl<M: Raycaster> LimitedSensor<Range<f64>> for DummyLidar<M> {
     fn range(&self) -> Option<Range<f64>> {
         self.range.clone()
     }
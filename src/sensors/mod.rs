@@ -1,5 +1,6 @@
-use crate::utility::Pose;
-use std::{marker::PhantomData, ops::{AddAssign, Sub}};
+use crate::utility::{Point, Pose};
+use rand::{thread_rng, Rng};
+use std::{marker::PhantomData, ops::{AddAssign, Range, Sub}, time::{Duration, Instant}};
 
 // pub mod gpio;
 pub mod dummy;
@@ -27,6 +28,12 @@ pub trait Sensor {
     fn relative_pose(&self) -> Pose {
         Pose::default()
     }
+    /// The minimum amount of time between new data from the sensor,
+    /// so that it need not be polled more often than that.
+    /// Returns `None` by default if the sensor has no minimum.
+    fn min_interval(&self) -> Option<Duration> {
+        None
+    }
 }
 
 /// General trait for sensors that can have limitations (e.g. a distance sensor has a maximum range)
@@ -95,6 +102,22 @@ pub trait WrappableSensor: Sensor + Sized {
     {
         MappedPoseSensor::new(self, map)
     }
+    /// Wraps `self` in a `FaultInjector` which injects no faults
+    /// until configured with its `with_*` functions.
+    ///
+    /// Implementations of `LimitedSensor` and `SensorSink` on `self` are reflected up.
+    fn inject_faults(self) -> FaultInjector<Self>
+    where
+        <Self as Sensor>::Output: Clone + Default + Spike,
+    {
+        FaultInjector::new(self)
+    }
+    /// Wraps `self` in a `MaskedSensor` with no masked regions.
+    ///
+    /// Implementations of `LimitedSensor` and `SensorSink` on `self` are reflected up.
+    fn masked(self) -> MaskedSensor<Self> {
+        MaskedSensor::new(self)
+    }
 }
 
 impl<S: Sensor + Sized> WrappableSensor for S {}
@@ -183,6 +206,10 @@ where
     fn sense(&self) -> Self::Output {
         self.sink_1.sense()
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.sink_1.min_interval()
+    }
 }
 
 impl<S1, S2, I, R> LimitedSensor<R> for ZippedSensorSink<S1, S2, I>
@@ -258,6 +285,10 @@ where
     fn relative_pose(&self) -> Pose {
         self.internal_sensor_sink.relative_pose()
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.internal_sensor_sink.min_interval()
+    }
 }
 
 impl<S, I, Map, MappedIn, R> LimitedSensor<R> for MappedSensorSink<S, I, Map, MappedIn>
@@ -316,6 +347,10 @@ where
     fn relative_pose(&self) -> Pose {
         self.internal_sensor.relative_pose()
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.internal_sensor.min_interval()
+    }
 }
 
 impl<S, O, Map, MappedOut, R> LimitedSensor<R> for MappedSensor<S, O, Map, MappedOut>
@@ -394,6 +429,10 @@ where
     fn relative_pose(&self) -> Pose {
         self.internal_sensor.relative_pose()
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.internal_sensor.min_interval()
+    }
 }
 
 impl<S, Map, MappedOut, R> LimitedSensor<R> for FullyMappedSensor<S, Map, MappedOut>
@@ -462,6 +501,10 @@ where
     fn relative_pose(&self) -> Pose {
         (self.map)(self.internal_sensor.relative_pose())
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.internal_sensor.min_interval()
+    }
 }
 
 impl<S, M, R> LimitedSensor<R> for MappedPoseSensor<S, M>
@@ -534,6 +577,10 @@ where
     fn relative_pose(&self) -> Pose {
         self.internal_sensor.relative_pose()
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.internal_sensor.min_interval()
+    }
 }
 
 impl<S, L> LimitedSensor<L> for OverridenLimitedSensor<S, L>
@@ -609,6 +656,10 @@ where
     fn relative_pose(&self) -> Pose {
         self.absolute_sensor.relative_pose()
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.absolute_sensor.min_interval()
+    }
 }
 
 /// A wrapper sensor that senses the total sum in `absolute_sensor`'s outputs.
@@ -654,4 +705,279 @@ where
     fn relative_pose(&self) -> Pose {
         self.absolute_sensor.relative_pose()
     }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.absolute_sensor.min_interval()
+    }
+}
+
+/// Data which can be corrupted by a spike of a given magnitude.
+pub trait Spike {
+    /// Offsets `self` by `magnitude` (which may be negative)
+    fn spike(self, magnitude: f64) -> Self;
+}
+
+impl Spike for f64 {
+    fn spike(self, magnitude: f64) -> Self {
+        self + magnitude
+    }
+}
+
+impl Spike for Point {
+    /// Offsets the distance of the point from the sensor, keeping its angle.
+    /// The distance is clamped at 0 rather than flipping the point through the sensor
+    fn spike(self, magnitude: f64) -> Self {
+        let mag = self.mag();
+        if mag == 0. {
+            return self;
+        }
+        self * ((mag + magnitude).max(0.) / mag)
+    }
+}
+
+impl Spike for Pose {
+    fn spike(self, magnitude: f64) -> Self {
+        self.with_position(self.position.spike(magnitude))
+    }
+}
+
+impl<T: Spike> Spike for Option<T> {
+    fn spike(self, magnitude: f64) -> Self {
+        self.map(|v| v.spike(magnitude))
+    }
+}
+
+impl<T: Spike> Spike for Vec<T> {
+    fn spike(self, magnitude: f64) -> Self {
+        self.into_iter().map(|v| v.spike(magnitude)).collect()
+    }
+}
+
+/// A wrapper sensor that randomly corrupts the output of `internal_sensor`,
+/// used to test how robust localizers are to misbehaving sensors.
+///
+/// Faults are rolled in `update`, in order of priority:
+/// - with probability `dropout_rate` the output is dropped (`Default::default()`, e.g. `None` or an empty scan)
+/// - with probability `stuck_rate` the output is stuck at the last value
+/// - with probability `spike_rate` the output is offset by ±`spike_magnitude`
+///
+/// The rest of the implementation of `Sensor`, `LimitedSensor` and `SensorSink` is reflected upward
+/// from `internal_sensor`.
+pub struct FaultInjector<S>
+where
+    S: Sensor,
+{
+    pub internal_sensor: S,
+    pub dropout_rate: f64,
+    pub stuck_rate: f64,
+    pub spike_rate: f64,
+    pub spike_magnitude: f64,
+    current: S::Output,
+}
+
+impl<S> FaultInjector<S>
+where
+    S: Sensor,
+    S::Output: Clone + Default + Spike,
+{
+    /// Creates a new `FaultInjector` around `internal_sensor` which injects no faults
+    pub fn new(internal_sensor: S) -> Self {
+        let current = internal_sensor.sense();
+        Self {
+            internal_sensor,
+            dropout_rate: 0.,
+            stuck_rate: 0.,
+            spike_rate: 0.,
+            spike_magnitude: 0.,
+            current,
+        }
+    }
+
+    pub fn with_dropout_rate(mut self, dropout_rate: f64) -> Self {
+        self.dropout_rate = dropout_rate;
+        self
+    }
+
+    pub fn with_stuck_rate(mut self, stuck_rate: f64) -> Self {
+        self.stuck_rate = stuck_rate;
+        self
+    }
+
+    pub fn with_spikes(mut self, spike_rate: f64, spike_magnitude: f64) -> Self {
+        self.spike_rate = spike_rate;
+        self.spike_magnitude = spike_magnitude;
+        self
+    }
+}
+
+impl<S> Sensor for FaultInjector<S>
+where
+    S: Sensor,
+    S::Output: Clone + Default + Spike,
+{
+    type Output = S::Output;
+
+    fn update(&mut self) {
+        self.internal_sensor.update();
+        let mut rng = thread_rng();
+        if rng.gen_bool(self.dropout_rate.min(1.).max(0.)) {
+            self.current = S::Output::default();
+        } else if !rng.gen_bool(self.stuck_rate.min(1.).max(0.)) {
+            self.current = self.internal_sensor.sense();
+            if rng.gen_bool(self.spike_rate.min(1.).max(0.)) {
+                let sign = if rng.gen() { 1. } else { -1. };
+                self.current = self.current.clone().spike(sign * self.spike_magnitude);
+            }
+        }
+    }
+
+    fn sense(&self) -> Self::Output {
+        self.current.clone()
+    }
+
+    fn relative_pose(&self) -> Pose {
+        self.internal_sensor.relative_pose()
+    }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.internal_sensor.min_interval()
+    }
+}
+
+impl<S, R> LimitedSensor<R> for FaultInjector<S>
+where
+    S: LimitedSensor<R>,
+    S::Output: Clone + Default + Spike,
+{
+    fn range(&self) -> Option<R> {
+        self.internal_sensor.range()
+    }
+}
+
+impl<S> SensorSink for FaultInjector<S>
+where
+    S: Sensor + SensorSink,
+    S::Output: Clone + Default + Spike,
+{
+    type Input = S::Input;
+
+    fn update_sink(&mut self) {
+        self.internal_sensor.update_sink();
+    }
+
+    fn push(&mut self, input: Self::Input) {
+        self.internal_sensor.push(input);
+    }
+}
+
+/// A sensor with world space regions in which its data should be ignored
+/// (e.g. regions occupied by dynamic obstacles from a tracker).
+///
+/// The data of the internal sensor is not changed, as whether a point is in a region
+/// depends on the pose of the robot. Instead, error calculators such as `masked_lidar_error`
+/// skip data which would fall in the regions given the pose of each particle.
+///
+/// Each region is a rectangle from `start` (its minimum corner) to `end` (its maximum corner).
+pub struct MaskedSensor<S> {
+    pub internal_sensor: S,
+    pub mask: Vec<Range<Point>>,
+}
+
+impl<S> MaskedSensor<S> {
+    pub fn new(internal_sensor: S) -> Self {
+        Self {
+            internal_sensor,
+            mask: Vec::new(),
+        }
+    }
+
+    /// Sets the regions to ignore, e.g. before each observation update
+    pub fn set_mask(&mut self, mask: Vec<Range<Point>>) {
+        self.mask = mask;
+    }
+
+    /// Whether `point` (in world space) is in any of the masked regions
+    pub fn is_masked(&self, point: Point) -> bool {
+        self.mask.iter().any(|region| {
+            (region.start.x..=region.end.x).contains(&point.x)
+                && (region.start.y..=region.end.y).contains(&point.y)
+        })
+    }
+}
+
+impl<S: Sensor> Sensor for MaskedSensor<S> {
+    type Output = S::Output;
+
+    fn update(&mut self) {
+        self.internal_sensor.update();
+    }
+
+    fn sense(&self) -> Self::Output {
+        self.internal_sensor.sense()
+    }
+
+    fn relative_pose(&self) -> Pose {
+        self.internal_sensor.relative_pose()
+    }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.internal_sensor.min_interval()
+    }
+}
+
+impl<S, R> LimitedSensor<R> for MaskedSensor<S>
+where
+    S: LimitedSensor<R>,
+{
+    fn range(&self) -> Option<R> {
+        self.internal_sensor.range()
+    }
+}
+
+impl<S> SensorSink for MaskedSensor<S>
+where
+    S: SensorSink,
+{
+    type Input = S::Input;
+
+    fn update_sink(&mut self) {
+        self.internal_sensor.update_sink();
+    }
+
+    fn push(&mut self, input: Self::Input) {
+        self.internal_sensor.push(input);
+    }
+}
+
+/// Decides when to poll a sensor so that it is not polled more often than its `Sensor::min_interval`
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PollScheduler {
+    last_poll: Option<Instant>,
+}
+
+impl PollScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `sensor` is due to be polled at `now`
+    pub fn is_due<S: Sensor>(&self, sensor: &S, now: Instant) -> bool {
+        match (self.last_poll, sensor.min_interval()) {
+            (Some(last_poll), Some(min_interval)) => {
+                now.saturating_duration_since(last_poll) >= min_interval
+            }
+            _ => true,
+        }
+    }
+
+    /// Updates `sensor` if it is due to be polled at `now`.
+    /// Returns whether the sensor was updated.
+    pub fn poll<S: Sensor>(&mut self, sensor: &mut S, now: Instant) -> bool {
+        if !self.is_due(sensor, now) {
+            return false;
+        }
+        sensor.update();
+        self.last_poll = Some(now);
+        true
+    }
 }
@@ -825,7 +825,7 @@ fn main() {
         ]));
 
         // Measurement update with sensor data
-        filter.measurement_update(
+        if let Err(e) = filter.measurement_update(
             RowVector6::from_vec(vec![
                 mcl_prediction.angle,
                 mcl_prediction.position.x,
@@ -835,7 +835,9 @@ fn main() {
                 motion_measurements.position.y,
             ]),
             r,
-        );
+        ) {
+            println!("Skipping measurement update: {}", e);
+        }
 
         // Calculate the squared error of each of the prediction mechanisms over time,
         // in angle and position.
@@ -77,6 +77,17 @@ impl Point {
     pub fn cross_mag(&self, other: Point) -> f64 {
         self.x * other.y - self.y * other.x
     }
+
+    /// Closest point to `self` on the line segment from `start` to `end`
+    pub fn closest_on_segment(&self, start: Point, end: Point) -> Point {
+        let line = end - start;
+        let len_sq = line.dot(line);
+        if len_sq == 0. {
+            return start;
+        }
+        let t = ((*self - start).dot(line) / len_sq).max(0.).min(1.);
+        start + line * t
+    }
 }
 
 impl Into<Point> for (f64, f64) {
@@ -617,7 +628,17 @@ impl KinematicState {
         false
     }
 
-    pub fn clamp(&mut self, range: Range<Point>) -> Pose {
+    /// Clamps the position of `self` to `range` without changing its velocity
+    pub fn clamp_position(&self, range: Range<Point>) -> KinematicState {
+        self.with_position(self.position.clamp(range.start, range.end))
+    }
+
+    /// Clamps the position of `self` to `range` as the result of a control update,
+    /// stopping the robot along each axis on which it hit the boundary
+    /// (and stopping its rotation if it hit the boundary at all).
+    ///
+    /// Returns the change in velocity caused by hitting the boundary
+    pub fn clamp_control_update(&mut self, range: Range<Point>) -> Pose {
         let clamped_position = self.position.clamp(range.start, range.end);
         if clamped_position == self.position {
             return Pose::default();
@@ -656,6 +677,18 @@ impl KinematicState {
         self.velocity = velocity;
         self
     }
+
+    /// Multiplies every component by `factor` (e.g. to scale a rate of change by a time step).
+    ///
+    /// Unlike `Mul`, does not normalize angle, as a scaled change in angle may be more than a full turn
+    pub fn scale(&self, factor: f64) -> KinematicState {
+        KinematicState {
+            angle: self.angle * factor,
+            position: self.position * factor,
+            vel_angle: self.vel_angle * factor,
+            velocity: self.velocity * factor,
+        }
+    }
 }
 
 impl From<RowVector6<f64>> for KinematicState {
@@ -743,6 +776,20 @@ impl std::ops::Div<f64> for KinematicState {
     }
 }
 
+impl std::ops::Mul<f64> for KinematicState {
+    type Output = KinematicState;
+
+    /// Does normalize angle
+    fn mul(self, other: f64) -> KinematicState {
+        KinematicState {
+            angle: (self.angle * other) % (2. * PI),
+            position: self.position * other,
+            vel_angle: self.vel_angle * other,
+            velocity: self.velocity * other,
+        }
+    }
+}
+
 impl std::ops::AddAssign for KinematicState {
     /// Does not normalize angle
     fn add_assign(&mut self, other: KinematicState) {
@@ -773,12 +820,25 @@ impl Pose {
         }
     }
 
+    /// Creates a random pose within ±`range`.
+    ///
+    /// Components of `range` which are zero are exactly zero rather than sampled
     pub fn random_from_range(range: Pose) -> Pose {
-        Pose::random(
-            -range.angle..range.angle,
-            -range.position.x..range.position.x,
-            -range.position.y..range.position.y,
-        )
+        let mut rng = thread_rng();
+        let mut sample = |margin: f64| {
+            if margin == 0. {
+                0.
+            } else {
+                rng.gen_range(-margin..margin)
+            }
+        };
+        Pose {
+            angle: sample(range.angle),
+            position: Point {
+                x: sample(range.position.x),
+                y: sample(range.position.y),
+            },
+        }
     }
 
     /// Mod `angle` by 2π
@@ -809,6 +869,52 @@ impl Pose {
         self.position = position;
         self
     }
+
+    /// Multiplies the angle and position by `factor` (e.g. to scale a control delta by a time step).
+    ///
+    /// Unlike `Mul`, does not normalize angle, as a scaled change in angle may be more than a full turn
+    pub fn scale(&self, factor: f64) -> Pose {
+        Pose {
+            angle: self.angle * factor,
+            position: self.position * factor,
+        }
+    }
+
+    /// Whether the angle and position of `self` are all finite (not NaN or infinite)
+    pub fn is_finite(&self) -> bool {
+        self.angle.is_finite() && self.position.x.is_finite() && self.position.y.is_finite()
+    }
+
+    /// The angle of the shortest arc between the angles of `self` and `other`, from 0 to π
+    pub fn angle_dist(&self, other: Pose) -> f64 {
+        let angle_diff = (other.angle - self.angle).rem_euclid(2. * PI);
+        angle_diff.min(2. * PI - angle_diff)
+    }
+
+    /// A single distance between poses, combining the distance between their positions with
+    /// the shortest arc between their angles (see `angle_dist`) multiplied by `angle_weight`,
+    /// i.e. how many units of distance a radian is worth
+    pub fn distance_to(&self, other: Pose, angle_weight: f64) -> f64 {
+        self.position
+            .dist(other.position)
+            .hypot(angle_weight * self.angle_dist(other))
+    }
+
+    /// Linearly interpolates between `self` (`t = 0`) and `other` (`t = 1`)
+    ///
+    /// The angle is interpolated along the shortest arc and is not normalized
+    pub fn lerp(self, other: Pose, t: f64) -> Pose {
+        let mut angle_diff = (other.angle - self.angle) % (2. * PI);
+        if angle_diff > PI {
+            angle_diff -= 2. * PI;
+        } else if angle_diff < -PI {
+            angle_diff += 2. * PI;
+        }
+        Pose {
+            angle: self.angle + angle_diff * t,
+            position: self.position + (other.position - self.position) * t,
+        }
+    }
 }
 
 impl std::ops::Add for Pose {